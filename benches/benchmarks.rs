@@ -4,7 +4,7 @@ extern crate criterion;
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput, BenchmarkGroup, BenchmarkId};
 
-use rand::{thread_rng, Rng, RngCore};
+use rand::{thread_rng, Rng};
 use rand::distributions::Alphanumeric;
 use gelf::{Message, UdpBackend, MessageCompression, Logger};
 use std::iter;
@@ -22,24 +22,6 @@ fn messages_iterator<'a>(characters: usize) -> impl std::iter::Iterator<Item=Mes
     iter::repeat_with(move || Message::new(short_message.clone()))
 }
 
-fn random_messages_iterator<'a>() -> impl std::iter::Iterator<Item=Message<'a>> {
-
-    iter::repeat_with(|| {
-        let characters = thread_rng().next_u32();
-
-        let short_message: String = thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(characters as usize)
-            .collect();
-
-        Message::new(short_message)
-    })
-}
-
-fn generate_messages<'a>(size: usize, characters: usize) -> Vec<Message<'a>> {
-   messages_iterator(characters).take(size).collect()
-}
-
 fn log_message_characters_benchmark(logger: Logger, group: &mut BenchmarkGroup<WallTime>) {
     for size in [100, 200, 500, 1000].iter() {
         let mut iterator = messages_iterator(*size);
@@ -49,7 +31,7 @@ fn log_message_characters_benchmark(logger: Logger, group: &mut BenchmarkGroup<W
             b.iter(|| {
                 let next = iterator.next().expect("New item");
 
-                black_box(logger.log_message(black_box(next)))
+                logger.log_message(black_box(next))
             })
         });
     }
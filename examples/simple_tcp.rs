@@ -26,7 +26,7 @@ use shared::*;
 const LOG_FILTER: LogLevelFilter = LogLevelFilter::Trace;
 
 /// Set the hostname which should be used in the GELF messages
-static HOSTNAME: &'static str = "test.local";
+static HOSTNAME: &str = "test.local";
 
 fn main() {
     // Default options:
@@ -74,7 +74,7 @@ fn main() {
                 .as_path()
                 .to_string_lossy(),
         ),
-    );
+    ).expect("Failed to set default metadata");
 
     // Install the logger as a system logger
     logger
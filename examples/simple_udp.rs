@@ -34,7 +34,7 @@ const MESSAGE_COMPRESSION: MessageCompression = MessageCompression::None;
 const CHUNK_SIZE: ChunkSize = ChunkSize::LAN;
 
 /// Set the hostname which should be used in the GELF messages
-static HOSTNAME: &'static str = "test.local";
+static HOSTNAME: &str = "test.local";
 
 fn main() {
     // Default options:
@@ -71,7 +71,7 @@ fn main() {
                 .as_path()
                 .to_string_lossy(),
         ),
-    );
+    ).expect("Failed to set default metadata");
 
     // Install the logger as a system logger
     logger
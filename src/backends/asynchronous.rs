@@ -0,0 +1,478 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::Fail;
+
+use crate::{Backend, Error, Result, WireMessage};
+
+/// Default time `AsyncBackend` waits for the queue to drain on drop
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// OverflowPolicy defines what happens when a message is logged to a full `AsyncBackend`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    /// Discard the message which is about to be logged
+    DropNewest,
+    /// Discard the oldest queued message to make room for the new one
+    DropOldest,
+    /// Wait up to the given duration for free space, then discard the new message
+    Block(Duration),
+}
+
+/// AsyncBackend moves the transport of messages off the caller's thread
+///
+/// Messages are pushed into a bounded queue which is drained by a dedicated
+/// thread into the wrapped backend. Serialization, compression and I/O of the
+/// wrapped backend therefore never block the logging thread.
+///
+/// When the queue is full the `OverflowPolicy` decides which message is
+/// discarded. Errors of the wrapped backend can't be reported to the caller
+/// and are only counted.
+///
+/// `flush` waits until the queue is drained and the wrapped backend is
+/// flushed. On drop the backend waits until the queue is drained or the
+/// drain timeout expires. Messages still queued after the timeout are
+/// discarded.
+pub struct AsyncBackend {
+    shared: Arc<Shared>,
+    worker: Option<thread::JoinHandle<()>>,
+    overflow_policy: OverflowPolicy,
+    drain_timeout: Duration,
+}
+
+/// State shared between the `AsyncBackend` and its worker thread
+struct Shared {
+    state: Mutex<State>,
+    capacity: usize,
+    not_empty: Condvar,
+    not_full: Condvar,
+    stopped: Condvar,
+    flushed: Condvar,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+}
+
+struct State {
+    queue: VecDeque<WireMessage<'static>>,
+    flush_requested: u64,
+    flush_completed: u64,
+    flush_error: Option<String>,
+    shutdown_deadline: Option<Instant>,
+    stopped: bool,
+}
+
+/// A task for the worker thread
+enum Task {
+    Send(WireMessage<'static>),
+    Flush(u64),
+}
+
+impl AsyncBackend {
+    /// Construct a new AsyncBackend wrapping `backend` with a queue of `capacity` messages
+    ///
+    /// The overflow policy defaults to `OverflowPolicy::DropNewest`.
+    pub fn new(backend: Box<dyn Backend>, capacity: usize) -> Result<AsyncBackend> {
+        if capacity == 0 {
+            return Err(format_err!("The queue capacity must be greater than 0")
+                .context(Error::BackendCreationFailed)
+                .into());
+        }
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::with_capacity(capacity),
+                flush_requested: 0,
+                flush_completed: 0,
+                flush_error: None,
+                shutdown_deadline: None,
+                stopped: false,
+            }),
+            capacity,
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            stopped: Condvar::new(),
+            flushed: Condvar::new(),
+            dropped: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        });
+
+        let worker_shared = shared.clone();
+        let worker = thread::Builder::new()
+            .name("gelf-async-backend".into())
+            .spawn(move || worker_shared.run(backend))
+            .map_err(|e| {
+                e.context("Failed to spawn the sender thread")
+                    .context(Error::BackendCreationFailed)
+            })?;
+
+        Ok(AsyncBackend {
+            shared,
+            worker: Some(worker),
+            overflow_policy: OverflowPolicy::DropNewest,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+        })
+    }
+
+    /// Return the current overflow policy
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    /// Set the overflow policy
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) -> &mut Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Return the time waited for the queue to drain on flush and drop
+    pub fn drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+
+    /// Set the time waited for the queue to drain on flush and drop
+    pub fn set_drain_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Return the number of messages currently waiting in the queue
+    pub fn queued_messages(&self) -> usize {
+        self.shared.state.lock().unwrap().queue.len()
+    }
+
+    /// Return the number of messages discarded because the queue was full
+    pub fn dropped_messages(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of messages the wrapped backend failed to log
+    pub fn failed_messages(&self) -> u64 {
+        self.shared.failed.load(Ordering::Relaxed)
+    }
+}
+
+impl Backend for AsyncBackend {
+    /// Queue a message for the sender thread.
+    ///
+    /// This only fails if the overflow policy is `OverflowPolicy::Block` and no
+    /// space became available in time.
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        let msg = msg.into_owned();
+        let mut state = self.shared.state.lock().unwrap();
+
+        if state.queue.len() >= self.shared.capacity {
+            match self.overflow_policy {
+                OverflowPolicy::DropNewest => {
+                    self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                OverflowPolicy::DropOldest => {
+                    state.queue.pop_front();
+                    self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                OverflowPolicy::Block(timeout) => {
+                    let deadline = Instant::now() + timeout;
+
+                    while state.queue.len() >= self.shared.capacity {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                            return Err(format_err!("Timed out waiting for space in the queue")
+                                .context(Error::LogTransmitFailed)
+                                .into());
+                        }
+
+                        state = self.shared.not_full.wait_timeout(state, deadline - now).unwrap().0;
+                    }
+                }
+            }
+        }
+
+        state.queue.push_back(msg);
+        self.shared.not_empty.notify_one();
+
+        Ok(())
+    }
+
+    /// Wait until all queued messages are sent and the wrapped backend is flushed.
+    ///
+    /// This fails if the wrapped backend fails to flush or the drain timeout
+    /// expires first.
+    fn flush(&self) -> Result<()> {
+        let deadline = Instant::now() + self.drain_timeout;
+
+        let mut state = self.shared.state.lock().unwrap();
+        state.flush_requested += 1;
+        let ticket = state.flush_requested;
+        self.shared.not_empty.notify_one();
+
+        while state.flush_completed < ticket {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(format_err!("Timed out waiting for the queue to drain")
+                    .context(Error::LogTransmitFailed)
+                    .into());
+            }
+
+            state = self.shared.flushed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+
+        match state.flush_error {
+            Some(ref error) => Err(format_err!("{}", error)
+                .context(Error::LogTransmitFailed)
+                .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AsyncBackend {
+    /// Drain the queue into the wrapped backend before shutting down the sender thread
+    fn drop(&mut self) {
+        let deadline = Instant::now() + self.drain_timeout;
+
+        let mut state = self.shared.state.lock().unwrap();
+        state.shutdown_deadline = Some(deadline);
+        self.shared.not_empty.notify_all();
+
+        while !state.stopped {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            state = self.shared.stopped.wait_timeout(state, deadline - now).unwrap().0;
+        }
+
+        let stopped = state.stopped;
+        drop(state);
+
+        match self.worker.take() {
+            Some(worker) if stopped => {
+                worker.join().unwrap_or_else(|_| warn!("The GELF sender thread panicked"));
+            }
+            // The worker is stuck in the wrapped backend: detach it
+            _ => warn!("Failed to drain the GELF message queue in time"),
+        }
+    }
+}
+
+impl Shared {
+    /// Feed queued messages into `backend` until shutdown
+    fn run(&self, backend: Box<dyn Backend>) {
+        while let Some(task) = self.next_task() {
+            match task {
+                Task::Send(msg) => {
+                    if backend.log_message(msg).is_err() {
+                        self.failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                Task::Flush(ticket) => {
+                    let result = backend.flush();
+
+                    let mut state = self.state.lock().unwrap();
+                    state.flush_completed = ticket;
+                    state.flush_error = result.err().map(|e| e.to_string());
+                    self.flushed.notify_all();
+                }
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.stopped = true;
+        self.stopped.notify_all();
+    }
+
+    /// Wait for the next message to send or a flush once the queue is drained
+    ///
+    /// Returns `None` once the queue is drained after shutdown was requested,
+    /// or when the drain deadline has passed.
+    fn next_task(&self) -> Option<Task> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if let Some(deadline) = state.shutdown_deadline {
+                if Instant::now() >= deadline {
+                    self.dropped.fetch_add(state.queue.len() as u64, Ordering::Relaxed);
+                    state.queue.clear();
+                    return None;
+                }
+            }
+
+            if let Some(msg) = state.queue.pop_front() {
+                self.not_full.notify_one();
+                return Some(Task::Send(msg));
+            }
+
+            if state.flush_requested > state.flush_completed {
+                return Some(Task::Flush(state.flush_requested));
+            }
+
+            if state.shutdown_deadline.is_some() {
+                return None;
+            }
+
+            state = self.not_empty.wait(state).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use crate::backends::test_util::{log, GatedBackend};
+
+    fn gated_backend(capacity: usize) -> (AsyncBackend, mpsc::Receiver<String>, mpsc::Sender<()>) {
+        let (backend, sent, permits) = GatedBackend::new();
+
+        (AsyncBackend::new(Box::new(backend), capacity).unwrap(), sent, permits)
+    }
+
+    fn wait_until_queued(backend: &AsyncBackend, num: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while backend.queued_messages() != num {
+            assert!(Instant::now() < deadline, "Queue never reached {} messages", num);
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn sends_messages_in_order() {
+        let (backend, sent, permits) = gated_backend(10);
+
+        for i in 0..5 {
+            log(&backend, &i.to_string()).unwrap();
+            permits.send(()).unwrap();
+        }
+
+        let received: Vec<String> = sent.iter().take(5).collect();
+        assert_eq!(received, vec!["0", "1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn drop_newest_on_overflow() {
+        let (backend, sent, permits) = gated_backend(2);
+
+        // The first message is taken by the sender thread and blocks there
+        log(&backend, "0").unwrap();
+        wait_until_queued(&backend, 0);
+
+        for i in 1..5 {
+            log(&backend, &i.to_string()).unwrap();
+        }
+
+        assert_eq!(backend.dropped_messages(), 2);
+
+        for _ in 0..3 {
+            permits.send(()).unwrap();
+        }
+
+        let received: Vec<String> = sent.iter().take(3).collect();
+        assert_eq!(received, vec!["0", "1", "2"]);
+    }
+
+    #[test]
+    fn drop_oldest_on_overflow() {
+        let (mut backend, sent, permits) = gated_backend(2);
+        backend.set_overflow_policy(OverflowPolicy::DropOldest);
+
+        log(&backend, "0").unwrap();
+        wait_until_queued(&backend, 0);
+
+        for i in 1..5 {
+            log(&backend, &i.to_string()).unwrap();
+        }
+
+        assert_eq!(backend.dropped_messages(), 2);
+
+        for _ in 0..3 {
+            permits.send(()).unwrap();
+        }
+
+        let received: Vec<String> = sent.iter().take(3).collect();
+        assert_eq!(received, vec!["0", "3", "4"]);
+    }
+
+    #[test]
+    fn block_with_timeout_on_overflow() {
+        let (mut backend, sent, permits) = gated_backend(1);
+        backend.set_overflow_policy(OverflowPolicy::Block(Duration::from_millis(50)));
+        backend.set_drain_timeout(Duration::from_millis(10));
+
+        log(&backend, "0").unwrap();
+        wait_until_queued(&backend, 0);
+        log(&backend, "1").unwrap();
+
+        let start = Instant::now();
+        assert!(log(&backend, "2").is_err());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(backend.dropped_messages(), 1);
+
+        // The receivers have to outlive the backend and its sender thread
+        drop(backend);
+        drop(permits);
+        drop(sent);
+    }
+
+    #[test]
+    fn flush_waits_for_queue_and_wrapped_backend() {
+        let (backend, sent, permits) = gated_backend(10);
+
+        for i in 0..3 {
+            log(&backend, &i.to_string()).unwrap();
+        }
+
+        thread::scope(|scope| {
+            let flush = scope.spawn(|| backend.flush());
+
+            thread::sleep(Duration::from_millis(50));
+            assert!(!flush.is_finished());
+
+            for _ in 0..3 {
+                permits.send(()).unwrap();
+            }
+            flush.join().unwrap().unwrap();
+        });
+
+        let received: Vec<String> = sent.try_iter().collect();
+        assert_eq!(received, vec!["0", "1", "2", "flush"]);
+    }
+
+    #[test]
+    fn drain_queue_on_drop() {
+        let (backend, sent, permits) = gated_backend(10);
+
+        for i in 0..5 {
+            log(&backend, &i.to_string()).unwrap();
+            permits.send(()).unwrap();
+        }
+
+        drop(backend);
+
+        let received: Vec<String> = sent.try_iter().collect();
+        assert_eq!(received, vec!["0", "1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn stop_draining_after_deadline() {
+        let (mut backend, sent, permits) = gated_backend(10);
+        backend.set_drain_timeout(Duration::from_millis(50));
+
+        for i in 0..5 {
+            log(&backend, &i.to_string()).unwrap();
+        }
+        permits.send(()).unwrap();
+
+        let start = Instant::now();
+        drop(backend);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // Release the detached sender thread
+        drop(permits);
+        assert_eq!(sent.recv().unwrap(), "0");
+    }
+}
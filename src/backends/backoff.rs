@@ -0,0 +1,83 @@
+use std::cmp;
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Backoff calculates the delays between retries of a failing operation
+///
+/// The delay starts at `min` and doubles with every failed attempt until it
+/// reaches `max`. A random jitter of up to half the delay is subtracted to
+/// avoid many senders retrying in lockstep.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    /// Construct a new Backoff with the given delay bounds
+    pub fn new(min: Duration, max: Duration) -> Backoff {
+        let max = cmp::max(min, max);
+
+        Backoff {
+            min,
+            max,
+            current: min,
+        }
+    }
+
+    /// Return the minimum delay
+    pub fn min(&self) -> Duration {
+        self.min
+    }
+
+    /// Return the maximum delay
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Return the delay until the next attempt and increase the following one
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = cmp::min(self.current.saturating_mul(2), self.max);
+
+        let jitter = rand::thread_rng().gen_range(0.0, 0.5);
+        delay.mul_f64(1.0 - jitter)
+    }
+
+    /// Reset the delay to its minimum after a successful attempt
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_grow_exponentially_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(1000));
+
+        let expected_max = [100, 200, 400, 800, 1000, 1000];
+        for max in expected_max.iter() {
+            let delay = backoff.next_delay();
+            assert!(delay <= Duration::from_millis(*max), "{:?} > {}ms", delay, max);
+            assert!(delay >= Duration::from_millis(*max / 2), "{:?} < {}ms", delay, max / 2);
+        }
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn saturate_at_huge_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(u64::MAX / 4), Duration::MAX);
+
+        for _ in 0..4 {
+            backoff.next_delay();
+        }
+        assert_eq!(backoff.current, Duration::MAX);
+    }
+}
@@ -0,0 +1,265 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::{Backend, Error, Result, WireMessage};
+use crate::backends::health::HealthTracker;
+use crate::util::hash;
+
+/// Number of points every backend gets on the consistent hash ring
+const VIRTUAL_NODES: usize = 160;
+
+/// BalanceStrategy defines how messages are spread across backends
+#[derive(Clone, Debug, PartialEq)]
+pub enum BalanceStrategy {
+    /// Send messages to the backends in turn
+    RoundRobin,
+    /// Send every message to a randomly chosen backend
+    Random,
+    /// Send all messages with the same value of an additional field to the same backend
+    ///
+    /// Messages without the field are distributed round-robin.
+    ConsistentHash { field: String },
+}
+
+/// BalancedBackend spreads messages across several backends
+///
+/// Each message is sent to a single backend chosen by the `BalanceStrategy`.
+/// If the chosen backend fails, the message is sent to the next one (in
+/// ring order for consistent hashing). Backends are marked unhealthy after a
+/// number of consecutive failures and skipped until a cooldown passed.
+pub struct BalancedBackend {
+    backends: Vec<Box<dyn Backend>>,
+    strategy: BalanceStrategy,
+    health: HealthTracker,
+    next: AtomicUsize,
+    ring: Vec<(u64, usize)>,
+}
+
+impl BalancedBackend {
+    /// Construct a new BalancedBackend with the given strategy
+    pub fn new(backends: Vec<Box<dyn Backend>>, strategy: BalanceStrategy) -> Result<BalancedBackend> {
+        if backends.is_empty() {
+            return Err(format_err!("At least one backend is required")
+                .context(Error::BackendCreationFailed)
+                .into());
+        }
+
+        let mut ring: Vec<(u64, usize)> = (0..backends.len())
+            .flat_map(|index| (0..VIRTUAL_NODES).map(move |node| (hash(format!("{}-{}", index, node).as_bytes()), index)))
+            .collect();
+        ring.sort_unstable();
+
+        Ok(BalancedBackend {
+            health: HealthTracker::new(backends.len()),
+            backends,
+            strategy,
+            next: AtomicUsize::new(0),
+            ring,
+        })
+    }
+
+    /// Return the balancing strategy
+    pub fn strategy(&self) -> &BalanceStrategy {
+        &self.strategy
+    }
+
+    /// Return the number of consecutive failures after which a backend is unhealthy
+    pub fn failure_threshold(&self) -> u32 {
+        self.health.failure_threshold
+    }
+
+    /// Set the number of consecutive failures after which a backend is unhealthy
+    pub fn set_failure_threshold(&mut self, threshold: u32) -> &mut Self {
+        self.health.failure_threshold = threshold;
+        self
+    }
+
+    /// Return the time until an unhealthy backend is probed again
+    pub fn cooldown(&self) -> Duration {
+        self.health.cooldown
+    }
+
+    /// Set the time until an unhealthy backend is probed again
+    pub fn set_cooldown(&mut self, cooldown: Duration) -> &mut Self {
+        self.health.cooldown = cooldown;
+        self
+    }
+
+    /// Return whether the backend at `index` is healthy
+    pub fn is_healthy(&self, index: usize) -> bool {
+        self.health.is_healthy(index)
+    }
+
+    /// Return the order in which the backends are tried for a message
+    fn order(&self, msg: &WireMessage) -> Vec<usize> {
+        let len = self.backends.len();
+
+        let key = match &self.strategy {
+            BalanceStrategy::ConsistentHash { field } => msg.message().metadata(field).map(|value| value.to_string()),
+            _ => None,
+        };
+
+        if let Some(key) = key {
+            return self.ring_order(hash(key.as_bytes()));
+        }
+
+        let start = match self.strategy {
+            BalanceStrategy::Random => rand::thread_rng().gen_range(0, len),
+            _ => self.next.fetch_add(1, Ordering::Relaxed) % len,
+        };
+
+        (0..len).map(|offset| (start + offset) % len).collect()
+    }
+
+    /// Return the backends in the order they appear on the ring after `hash`
+    fn ring_order(&self, hash: u64) -> Vec<usize> {
+        let start = self.ring.partition_point(|&(point, _)| point < hash);
+        let mut order = Vec::with_capacity(self.backends.len());
+
+        for offset in 0..self.ring.len() {
+            let index = self.ring[(start + offset) % self.ring.len()].1;
+            if !order.contains(&index) {
+                order.push(index);
+                if order.len() == self.backends.len() {
+                    break;
+                }
+            }
+        }
+
+        order
+    }
+}
+
+impl Backend for BalancedBackend {
+    /// Log a message to the backend chosen by the strategy
+    ///
+    /// Fails if all backends fail.
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        let order = self.order(&msg);
+        self.health.log_to_first(&self.backends, &order, msg)?;
+
+        Ok(())
+    }
+
+    /// Flush all backends
+    fn flush(&self) -> Result<()> {
+        let mut result = Ok(());
+        for backend in self.backends.iter() {
+            if let Err(e) = backend.flush() {
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Message;
+    use crate::backends::test_util::{log, wire_message, RecordingBackend};
+
+    fn balanced(num: usize, strategy: BalanceStrategy) -> (BalancedBackend, Vec<RecordingBackend>) {
+        let children: Vec<RecordingBackend> = (0..num).map(|_| RecordingBackend::default()).collect();
+        let backends = children.iter()
+            .map(|child| Box::new(child.clone()) as Box<dyn Backend>)
+            .collect();
+
+        (BalancedBackend::new(backends, strategy).unwrap(), children)
+    }
+
+    fn log_request(backend: &BalancedBackend, short_message: &str, request_id: &str) {
+        let mut message = Message::new(short_message.to_string());
+        message.set_metadata("request_id", request_id.to_string()).unwrap();
+
+        backend.log_message(wire_message(message)).unwrap();
+    }
+
+    #[test]
+    fn distribute_round_robin() {
+        let (backend, children) = balanced(3, BalanceStrategy::RoundRobin);
+
+        for i in 0..6 {
+            log(&backend, &i.to_string()).unwrap();
+        }
+
+        assert_eq!(children[0].sent(), vec!["0", "3"]);
+        assert_eq!(children[1].sent(), vec!["1", "4"]);
+        assert_eq!(children[2].sent(), vec!["2", "5"]);
+    }
+
+    #[test]
+    fn distribute_randomly() {
+        let (backend, children) = balanced(2, BalanceStrategy::Random);
+
+        for i in 0..100 {
+            log(&backend, &i.to_string()).unwrap();
+        }
+
+        assert_eq!(children[0].sent().len() + children[1].sent().len(), 100);
+        assert!(!children[0].sent().is_empty());
+        assert!(!children[1].sent().is_empty());
+    }
+
+    #[test]
+    fn hash_same_field_value_to_same_backend() {
+        let (backend, children) = balanced(4, BalanceStrategy::ConsistentHash { field: "request_id".into() });
+
+        for request in 0..50 {
+            for i in 0..3 {
+                log_request(&backend, &format!("{}-{}", request, i), &request.to_string());
+            }
+        }
+
+        for child in children.iter() {
+            let sent = child.sent();
+            assert!(!sent.is_empty(), "All backends should receive messages");
+
+            for message in sent.iter() {
+                let request = message.split('-').next().unwrap();
+                assert_eq!(sent.iter().filter(|m| m.starts_with(&format!("{}-", request))).count(), 3);
+            }
+        }
+    }
+
+    #[test]
+    fn keep_keys_of_healthy_backends_when_one_fails() {
+        let (mut backend, children) = balanced(3, BalanceStrategy::ConsistentHash { field: "request_id".into() });
+        backend.set_failure_threshold(1);
+
+        let targets: Vec<usize> = (0..30).map(|request| backend.ring_order(hash(request.to_string().as_bytes()))[0]).collect();
+
+        children[1].set_failing(true);
+        for request in 0..30 {
+            log_request(&backend, &request.to_string(), &request.to_string());
+        }
+        assert!(!backend.is_healthy(1));
+
+        for (request, target) in targets.iter().enumerate() {
+            let request = request.to_string();
+            if *target != 1 {
+                assert!(children[*target].sent().contains(&request));
+            } else {
+                assert!(!children[1].sent().contains(&request));
+                assert!(children[0].sent().contains(&request) || children[2].sent().contains(&request));
+            }
+        }
+    }
+
+    #[test]
+    fn skip_unhealthy_backends_round_robin() {
+        let (mut backend, children) = balanced(2, BalanceStrategy::RoundRobin);
+        backend.set_failure_threshold(1);
+        children[0].set_failing(true);
+
+        for i in 0..4 {
+            log(&backend, &i.to_string()).unwrap();
+        }
+
+        assert!(!backend.is_healthy(0));
+        assert_eq!(children[1].sent(), vec!["0", "1", "2", "3"]);
+    }
+}
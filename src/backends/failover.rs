@@ -0,0 +1,152 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::{Backend, Error, Result, WireMessage};
+use crate::backends::health::HealthTracker;
+
+/// FailoverBackend sends messages to the first healthy of several backends
+///
+/// The backends are ordered by priority. Every message is sent to the first
+/// healthy backend; if it fails, the next one is tried. A backend is marked
+/// unhealthy after a number of consecutive failures and skipped until a
+/// cooldown passed. Then it is probed with the next message and takes over
+/// again once it succeeds.
+pub struct FailoverBackend {
+    backends: Vec<Box<dyn Backend>>,
+    order: Vec<usize>,
+    health: HealthTracker,
+    active: AtomicUsize,
+}
+
+impl FailoverBackend {
+    /// Construct a new FailoverBackend with backends ordered by priority
+    pub fn new(backends: Vec<Box<dyn Backend>>) -> Result<FailoverBackend> {
+        if backends.is_empty() {
+            return Err(format_err!("At least one backend is required")
+                .context(Error::BackendCreationFailed)
+                .into());
+        }
+
+        Ok(FailoverBackend {
+            order: (0..backends.len()).collect(),
+            health: HealthTracker::new(backends.len()),
+            backends,
+            active: AtomicUsize::new(0),
+        })
+    }
+
+    /// Return the number of consecutive failures after which a backend is unhealthy
+    pub fn failure_threshold(&self) -> u32 {
+        self.health.failure_threshold
+    }
+
+    /// Set the number of consecutive failures after which a backend is unhealthy
+    pub fn set_failure_threshold(&mut self, threshold: u32) -> &mut Self {
+        self.health.failure_threshold = threshold;
+        self
+    }
+
+    /// Return the time until an unhealthy backend is probed again
+    pub fn cooldown(&self) -> Duration {
+        self.health.cooldown
+    }
+
+    /// Set the time until an unhealthy backend is probed again
+    pub fn set_cooldown(&mut self, cooldown: Duration) -> &mut Self {
+        self.health.cooldown = cooldown;
+        self
+    }
+
+    /// Return the index of the backend which accepted the last message
+    pub fn active_backend(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Return whether the backend at `index` is healthy
+    pub fn is_healthy(&self, index: usize) -> bool {
+        self.health.is_healthy(index)
+    }
+}
+
+impl Backend for FailoverBackend {
+    /// Log a message to the first healthy backend
+    ///
+    /// Fails if all backends fail.
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        let index = self.health.log_to_first(&self.backends, &self.order, msg)?;
+        self.active.store(index, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Flush all backends
+    fn flush(&self) -> Result<()> {
+        let mut result = Ok(());
+        for backend in self.backends.iter() {
+            if let Err(e) = backend.flush() {
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::backends::test_util::{log, RecordingBackend};
+
+    #[test]
+    fn fail_over_to_secondary_and_back() {
+        let primary = RecordingBackend::default();
+        let secondary = RecordingBackend::default();
+
+        let mut backend = FailoverBackend::new(vec![Box::new(primary.clone()), Box::new(secondary.clone())]).unwrap();
+        backend
+            .set_failure_threshold(2)
+            .set_cooldown(Duration::from_millis(50));
+
+        log(&backend, "1").unwrap();
+        assert_eq!(backend.active_backend(), 0);
+
+        primary.set_failing(true);
+        log(&backend, "2").unwrap();
+        assert_eq!(backend.active_backend(), 1);
+        assert!(backend.is_healthy(0));
+
+        log(&backend, "3").unwrap();
+        assert!(!backend.is_healthy(0));
+
+        // The unhealthy primary is skipped during the cooldown
+        log(&backend, "4").unwrap();
+        assert_eq!(primary.attempts(), vec!["1", "2", "3"]);
+        assert_eq!(secondary.attempts(), vec!["2", "3", "4"]);
+
+        // The primary takes over again after a successful probe
+        primary.set_failing(false);
+        thread::sleep(Duration::from_millis(60));
+        log(&backend, "5").unwrap();
+        assert_eq!(backend.active_backend(), 0);
+        assert!(backend.is_healthy(0));
+    }
+
+    #[test]
+    fn fail_if_all_backends_fail() {
+        let primary = RecordingBackend::default();
+        primary.set_failing(true);
+
+        let mut backend = FailoverBackend::new(vec![Box::new(primary.clone())]).unwrap();
+        backend.set_failure_threshold(1);
+
+        assert!(log(&backend, "1").is_err());
+        assert!(!backend.is_healthy(0));
+
+        // Unhealthy backends are still tried if no other backend is available
+        assert!(log(&backend, "2").is_err());
+        assert_eq!(primary.attempts().len(), 2);
+
+        assert!(FailoverBackend::new(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{Backend, Error, Result, WireMessage};
+use crate::errors::FanoutError;
+
+/// FanoutBackend sends every message to several backends
+///
+/// All backends receive a clone of the message. The clones share their
+/// serialized and compressed forms, so backends with the same settings
+/// serialize and compress the message only once.
+///
+/// A failing backend doesn't stop the delivery to the others. All failures
+/// are reported together as a `FanoutError`.
+pub struct FanoutBackend {
+    backends: Vec<Box<dyn Backend>>,
+}
+
+impl FanoutBackend {
+    /// Construct a new FanoutBackend sending to all given backends
+    pub fn new(backends: Vec<Box<dyn Backend>>) -> Result<FanoutBackend> {
+        if backends.is_empty() {
+            return Err(format_err!("At least one backend is required")
+                .context(Error::BackendCreationFailed)
+                .into());
+        }
+
+        Ok(FanoutBackend { backends })
+    }
+
+    /// Run an operation on all backends and collect the failures
+    fn for_all<F>(&self, mut operation: F) -> Result<()>
+    where
+        F: FnMut(usize, &dyn Backend) -> Result<()>,
+    {
+        let failures: Vec<(usize, failure::Error)> = self.backends.iter()
+            .enumerate()
+            .filter_map(|(index, backend)| operation(index, backend.as_ref()).err().map(|e| (index, e)))
+            .collect();
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(FanoutError::new(failures).into())
+        }
+    }
+}
+
+impl Backend for FanoutBackend {
+    /// Log a message to all backends
+    ///
+    /// Fails with a `FanoutError` if any of the backends failed.
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        let last = self.backends.len() - 1;
+        let mut msg = Some(msg);
+
+        self.for_all(|index, backend| {
+            // Only clone the message for all but the last backend
+            let msg = if index == last {
+                msg.take().expect("Should only be taken once")
+            } else {
+                msg.as_ref().expect("Should not be taken yet").clone()
+            };
+
+            backend.log_message(msg)
+        })
+    }
+
+    /// Flush all backends
+    ///
+    /// Fails with a `FanoutError` if any of the backends failed.
+    fn flush(&self) -> Result<()> {
+        self.for_all(|_, backend| backend.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::MessageCompression;
+    use crate::backends::test_util::{log, RecordingBackend};
+
+    /// A backend which compresses messages and records them with the results
+    #[derive(Clone)]
+    struct CompressingBackend {
+        compression: MessageCompression,
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+        messages: Arc<Mutex<Vec<WireMessage<'static>>>>,
+    }
+
+    impl CompressingBackend {
+        fn new(compression: MessageCompression) -> CompressingBackend {
+            CompressingBackend {
+                compression,
+                sent: Arc::new(Mutex::new(Vec::new())),
+                messages: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+    }
+
+    impl Backend for CompressingBackend {
+        fn log_message(&self, msg: WireMessage) -> Result<()> {
+            self.sent.lock().unwrap().push(msg.to_compressed_gelf(self.compression)?);
+            self.messages.lock().unwrap().push(msg.into_owned());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn send_to_all_backends() {
+        let gzip = MessageCompression::Gzip { level: 1 };
+        let children = [
+            CompressingBackend::new(gzip),
+            CompressingBackend::new(MessageCompression::None),
+            CompressingBackend::new(gzip),
+        ];
+
+        let backend = FanoutBackend::new(children.iter().map(|c| Box::new(c.clone()) as Box<dyn Backend>).collect()).unwrap();
+        log(&backend, "mirrored").unwrap();
+
+        for child in children.iter() {
+            assert_eq!(child.sent.lock().unwrap().len(), 1);
+        }
+
+        assert_eq!(children[0].sent.lock().unwrap()[0], children[2].sent.lock().unwrap()[0]);
+
+        // All backends got the same cache, so the message was compressed only once
+        let first = children[0].messages.lock().unwrap()[0].clone();
+        for child in children[1..].iter() {
+            assert!(child.messages.lock().unwrap()[0].shares_cache_with(&first));
+        }
+        assert_eq!(first.compressed_forms(), 1);
+    }
+
+    #[test]
+    fn report_all_failed_backends() {
+        let children = [
+            RecordingBackend::default(),
+            RecordingBackend::default(),
+            RecordingBackend::default(),
+        ];
+        children[0].set_failing(true);
+        children[2].set_failing(true);
+
+        let backend = FanoutBackend::new(children.iter().map(|c| Box::new(c.clone()) as Box<dyn Backend>).collect()).unwrap();
+        let err = log(&backend, "partially delivered").unwrap_err();
+
+        let fanout_error = err.downcast_ref::<FanoutError>().expect("Should be a FanoutError");
+        assert_eq!(fanout_error.failed_backends(), vec![0, 2]);
+        assert_eq!(err.to_string(), "2 fanout backend(s) failed: [0] Backend is down; [2] Backend is down;");
+
+        // Delivery to the healthy backend wasn't interrupted
+        assert_eq!(children[1].sent(), vec!["partially delivered"]);
+    }
+}
@@ -0,0 +1,529 @@
+use failure::Fail;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{Backend, Error, MessageCompression, Result, WireMessage};
+
+/// Compression level for rotated files
+const ROTATED_COMPRESSION_LEVEL: i32 = 6;
+
+/// Largest part of a rotated file which is compressed at once
+const MAX_COMPRESSION_SEGMENT: u64 = 64 * 1024 * 1024;
+
+/// FileFraming defines how records are separated in a log file
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FileFraming {
+    /// One JSON document per line (NDJSON)
+    #[default]
+    Ndjson,
+    /// Records terminated by a null byte, as with GELF over TCP
+    NullDelimited,
+}
+
+/// SyncPolicy defines when written records are synced to disk
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SyncPolicy {
+    /// Leave syncing to the operating system
+    #[default]
+    Never,
+    /// Sync after every record
+    Always,
+    /// Sync after the given number of records
+    EveryRecords(u32),
+    /// Sync with the first record after the given interval has passed
+    Interval(Duration),
+}
+
+/// FileBackend appends GELF messages to a local file
+///
+/// Each message is written as a single record, either as a line of NDJSON
+/// or as a null-delimited frame.
+///
+/// The file can be rotated by size and/or age. Rotated files are renamed to
+/// `<path>.1`, `<path>.2`, ... (the lowest number being the newest one) and
+/// optionally compressed with gzip (`<path>.1.gz`). Only the configured number
+/// of rotated files is kept.
+///
+/// Rotated files are compressed by a background thread, so logging isn't
+/// blocked by it. `flush` waits for a running compression to finish.
+pub struct FileBackend {
+    path: PathBuf,
+    framing: FileFraming,
+    max_file_size: Option<u64>,
+    rotation_interval: Option<Duration>,
+    retention: usize,
+    compress_rotated: bool,
+    sync_policy: SyncPolicy,
+    state: Mutex<FileState>,
+    compression: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+/// The currently opened file
+struct FileState {
+    file: File,
+    size: u64,
+    created: SystemTime,
+    unsynced: u32,
+    last_sync: Instant,
+}
+
+impl FileBackend {
+    /// Construct a new FileBackend appending to the given file
+    ///
+    /// The file is created if it doesn't exist. By default files are never rotated.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<FileBackend> {
+        let path = path.as_ref().to_path_buf();
+
+        let state = FileState::open(&path).map_err(|e| {
+            e.context(format!("Failed to open '{}'", path.display()))
+                .context(Error::BackendCreationFailed)
+        })?;
+
+        Ok(FileBackend {
+            path,
+            framing: FileFraming::default(),
+            max_file_size: None,
+            rotation_interval: None,
+            retention: 5,
+            compress_rotated: false,
+            sync_policy: SyncPolicy::default(),
+            state: Mutex::new(state),
+            compression: Mutex::new(None),
+        })
+    }
+
+    /// Return the framing of records
+    pub fn framing(&self) -> FileFraming {
+        self.framing
+    }
+
+    /// Set the framing of records
+    pub fn set_framing(&mut self, framing: FileFraming) -> &mut Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Return the size in bytes after which the file is rotated
+    pub fn max_file_size(&self) -> Option<u64> {
+        self.max_file_size
+    }
+
+    /// Set the size in bytes after which the file is rotated
+    ///
+    /// A record is never split, the file is rotated before a record would exceed the size.
+    pub fn set_max_file_size(&mut self, size: Option<u64>) -> &mut Self {
+        self.max_file_size = size;
+        self
+    }
+
+    /// Return the age after which the file is rotated
+    pub fn rotation_interval(&self) -> Option<Duration> {
+        self.rotation_interval
+    }
+
+    /// Set the age after which the file is rotated
+    ///
+    /// The age is measured from the file's creation (or the backend's construction,
+    /// if the filesystem doesn't record creation times).
+    pub fn set_rotation_interval(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.rotation_interval = interval;
+        self
+    }
+
+    /// Return the number of rotated files which are kept
+    pub fn retention(&self) -> usize {
+        self.retention
+    }
+
+    /// Set the number of rotated files which are kept
+    pub fn set_retention(&mut self, retention: usize) -> &mut Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Return a flag whether rotated files are compressed with gzip
+    pub fn rotated_file_compression(&self) -> bool {
+        self.compress_rotated
+    }
+
+    /// Compress rotated files with gzip
+    pub fn enable_rotated_file_compression(&mut self) -> &mut Self {
+        self.compress_rotated = true;
+        self
+    }
+
+    /// Keep rotated files uncompressed
+    pub fn disable_rotated_file_compression(&mut self) -> &mut Self {
+        self.compress_rotated = false;
+        self
+    }
+
+    /// Return the policy when records are synced to disk
+    pub fn sync_policy(&self) -> SyncPolicy {
+        self.sync_policy
+    }
+
+    /// Set the policy when records are synced to disk
+    pub fn set_sync_policy(&mut self, policy: SyncPolicy) -> &mut Self {
+        self.sync_policy = policy;
+        self
+    }
+
+    /// Return the path of the `index`th rotated file
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let path = self.uncompressed_path(index);
+        if self.compress_rotated {
+            let mut path = path.into_os_string();
+            path.push(".gz");
+            path.into()
+        } else {
+            path
+        }
+    }
+
+    /// Return the path of the `index`th rotated file before compression
+    fn uncompressed_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    /// Return whether the file has to be rotated before writing `len` bytes
+    fn needs_rotation(&self, state: &FileState, len: usize) -> bool {
+        if state.size == 0 {
+            return false;
+        }
+
+        let too_large = self.max_file_size
+            .is_some_and(|max| state.size + len as u64 > max);
+        let too_old = self.rotation_interval
+            .is_some_and(|interval| state.created.elapsed().is_ok_and(|age| age >= interval));
+
+        too_large || too_old
+    }
+
+    /// Shift all rotated files, rotate the current file and open a new one
+    ///
+    /// If the new file can't be opened, the current file is moved back and
+    /// stays in use.
+    fn rotate(&self, state: &mut FileState) -> Result<()> {
+        state.file.sync_all()?;
+
+        // The previous file must be compressed before the rotated files are shifted
+        self.wait_for_compression();
+
+        if self.retention > 0 {
+            remove_if_exists(&self.rotated_path(self.retention))?;
+
+            for index in (1..self.retention).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+        }
+
+        let rotated = self.uncompressed_path(1);
+        fs::rename(&self.path, &rotated)?;
+
+        match FileState::open(&self.path) {
+            Ok(new_state) => *state = new_state,
+            Err(e) => {
+                fs::rename(&rotated, &self.path)?;
+                return Err(e.into());
+            }
+        }
+
+        if self.retention == 0 {
+            fs::remove_file(&rotated)?;
+        } else if self.compress_rotated {
+            let target = self.rotated_path(1);
+            let segment_size = self.max_file_size
+                .map_or(MAX_COMPRESSION_SEGMENT, |max| max.clamp(1, MAX_COMPRESSION_SEGMENT));
+            let handle = thread::Builder::new()
+                .name("gelf-file-compression".to_string())
+                .spawn(move || {
+                    compress_file(&rotated, &target, segment_size).unwrap_or_else(|e| {
+                        warn!("Failed to compress rotated GELF file '{}': {}", rotated.display(), e)
+                    })
+                })?;
+
+            *self.compression.lock().unwrap() = Some(handle);
+        }
+
+        Ok(())
+    }
+
+    /// Wait until the compression of the last rotated file has finished
+    fn wait_for_compression(&self) {
+        if let Some(handle) = self.compression.lock().unwrap().take() {
+            handle.join().unwrap_or_else(|_| warn!("The GELF file compression thread panicked"));
+        }
+    }
+
+    /// Sync the file if the policy demands it
+    fn sync(&self, state: &mut FileState) -> io::Result<()> {
+        state.unsynced += 1;
+
+        let due = match self.sync_policy {
+            SyncPolicy::Never => false,
+            SyncPolicy::Always => true,
+            SyncPolicy::EveryRecords(records) => state.unsynced >= records,
+            SyncPolicy::Interval(interval) => state.last_sync.elapsed() >= interval,
+        };
+
+        if due {
+            state.sync()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Backend for FileBackend {
+    /// Append a message to the file, rotating it if necessary
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        let mut record: Vec<u8> = msg.to_gelf()?.into();
+        record.push(match self.framing {
+            FileFraming::Ndjson => b'\n',
+            FileFraming::NullDelimited => 0x00,
+        });
+
+        let mut state = self.state.lock().unwrap();
+
+        if self.needs_rotation(&state, record.len()) {
+            self.rotate(&mut state).map_err(|e| {
+                e.context(format!("Failed to rotate '{}'", self.path.display()))
+                    .context(Error::LogTransmitFailed)
+            })?;
+        }
+
+        state.file.write_all(&record).map_err(|e| {
+            e.context(format!("Failed to write to '{}'", self.path.display()))
+                .context(Error::LogTransmitFailed)
+        })?;
+        state.size += record.len() as u64;
+
+        self.sync(&mut state).map_err(|e| {
+            e.context(format!("Failed to sync '{}'", self.path.display()))
+                .context(Error::LogTransmitFailed)
+        })?;
+
+        Ok(())
+    }
+
+    /// Sync all written records to disk and wait for a running compression
+    fn flush(&self) -> Result<()> {
+        self.state.lock().unwrap().sync().map_err(|e| {
+            e.context(format!("Failed to sync '{}'", self.path.display()))
+                .context(Error::LogTransmitFailed)
+        })?;
+
+        self.wait_for_compression();
+        Ok(())
+    }
+}
+
+impl Drop for FileBackend {
+    /// Let a running compression finish
+    fn drop(&mut self) {
+        self.wait_for_compression();
+    }
+}
+
+impl FileState {
+    /// Open a file for appending
+    fn open(path: &Path) -> io::Result<FileState> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+
+        Ok(FileState {
+            file,
+            size: metadata.len(),
+            created: metadata.created().unwrap_or_else(|_| SystemTime::now()),
+            unsynced: 0,
+            last_sync: Instant::now(),
+        })
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+
+        Ok(())
+    }
+}
+
+/// Compress `source` with gzip into `target` and remove `source`
+///
+/// At most `segment_size` bytes are held in memory. Each segment becomes a
+/// gzip member of its own, gzip readers decompress the members as one stream.
+fn compress_file(source: &Path, target: &Path, segment_size: u64) -> Result<()> {
+    let compression = MessageCompression::Gzip { level: ROTATED_COMPRESSION_LEVEL };
+    let mut input = File::open(source)?;
+    let mut output = File::create(target)?;
+    let mut members = 0;
+
+    loop {
+        let mut segment = Vec::new();
+        let len = (&mut input).take(segment_size).read_to_end(&mut segment)? as u64;
+
+        // An empty file still gets a single, empty member
+        if len == 0 && members > 0 {
+            break;
+        }
+
+        output.write_all(&compression.compress_bytes(segment)?)?;
+        members += 1;
+
+        if len < segment_size {
+            break;
+        }
+    }
+
+    output.sync_all()?;
+
+    fs::remove_file(source)?;
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::test_util::{log, short_message};
+
+    fn read_records(data: &[u8], delimiter: u8) -> Vec<String> {
+        assert_eq!(data.last(), Some(&delimiter), "Record is not terminated");
+
+        data[..data.len() - 1]
+            .split(|byte| *byte == delimiter)
+            .map(short_message)
+            .collect()
+    }
+
+    #[test]
+    fn append_ndjson_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gelf.log");
+        fs::write(&path, b"{\"short_message\":\"existing\"}\n").unwrap();
+
+        let backend = FileBackend::new(&path).unwrap();
+        log(&backend, "first").unwrap();
+        log(&backend, "second").unwrap();
+
+        assert_eq!(read_records(&fs::read(&path).unwrap(), b'\n'), vec!["existing", "first", "second"]);
+    }
+
+    #[test]
+    fn write_null_delimited_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gelf.log");
+
+        let mut backend = FileBackend::new(&path).unwrap();
+        backend
+            .set_framing(FileFraming::NullDelimited)
+            .set_sync_policy(SyncPolicy::Always);
+        log(&backend, "first").unwrap();
+        log(&backend, "second").unwrap();
+
+        assert_eq!(read_records(&fs::read(&path).unwrap(), 0), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn rotate_by_size_and_keep_retention_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gelf.log");
+
+        let mut backend = FileBackend::new(&path).unwrap();
+        log(&backend, "0").unwrap();
+        let record_len = fs::metadata(&path).unwrap().len();
+
+        // Two records per file
+        backend
+            .set_max_file_size(Some(record_len * 2))
+            .set_retention(2);
+        for i in 1..8 {
+            log(&backend, &i.to_string()).unwrap();
+        }
+
+        assert_eq!(read_records(&fs::read(&path).unwrap(), b'\n'), vec!["6", "7"]);
+        assert_eq!(read_records(&fs::read(backend.rotated_path(1)).unwrap(), b'\n'), vec!["4", "5"]);
+        assert_eq!(read_records(&fs::read(backend.rotated_path(2)).unwrap(), b'\n'), vec!["2", "3"]);
+        assert!(!backend.rotated_path(3).exists());
+    }
+
+    #[test]
+    fn compress_large_files_in_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, target) = (dir.path().join("gelf.log.1"), dir.path().join("gelf.log.1.gz"));
+        fs::write(&source, b"0123456789").unwrap();
+
+        compress_file(&source, &target, 4).unwrap();
+
+        let gzip = MessageCompression::Gzip { level: ROTATED_COMPRESSION_LEVEL };
+        let expected: Vec<u8> = [&b"0123"[..], b"4567", b"89"].iter()
+            .flat_map(|segment| gzip.compress_bytes(segment.to_vec()).unwrap())
+            .collect();
+        assert_eq!(fs::read(&target).unwrap(), expected);
+        assert!(!source.exists());
+    }
+
+    #[test]
+    fn rotate_by_age_and_compress_rotated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gelf.log");
+
+        let mut backend = FileBackend::new(&path).unwrap();
+        backend
+            .set_rotation_interval(Some(Duration::from_millis(50)))
+            .enable_rotated_file_compression();
+
+        log(&backend, "old").unwrap();
+        thread::sleep(Duration::from_millis(60));
+        log(&backend, "new").unwrap();
+        backend.flush().unwrap();
+
+        assert_eq!(backend.rotated_path(1), dir.path().join("gelf.log.1.gz"));
+
+        let compressed = fs::read(backend.rotated_path(1)).unwrap();
+        let mut decompressed = vec![0; 4096];
+        let size = libdeflater::Decompressor::new()
+            .gzip_decompress(&compressed, &mut decompressed)
+            .unwrap();
+
+        assert_eq!(read_records(&decompressed[..size], b'\n'), vec!["old"]);
+        assert!(!backend.uncompressed_path(1).exists());
+        assert_eq!(read_records(&fs::read(&path).unwrap(), b'\n'), vec!["new"]);
+    }
+
+    #[test]
+    fn sync_every_n_records() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut backend = FileBackend::new(dir.path().join("gelf.log")).unwrap();
+        backend.set_sync_policy(SyncPolicy::EveryRecords(3));
+
+        log(&backend, "1").unwrap();
+        log(&backend, "2").unwrap();
+        assert_eq!(backend.state.lock().unwrap().unsynced, 2);
+
+        log(&backend, "3").unwrap();
+        assert_eq!(backend.state.lock().unwrap().unsynced, 0);
+
+        log(&backend, "4").unwrap();
+        backend.flush().unwrap();
+        assert_eq!(backend.state.lock().unwrap().unsynced, 0);
+    }
+}
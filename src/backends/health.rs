@@ -0,0 +1,133 @@
+use failure::Fail;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{Backend, Error, Result, WireMessage};
+
+/// Default number of consecutive failures after which a backend is unhealthy
+const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+/// Default time until an unhealthy backend is probed again
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// HealthTracker tracks the health of the children of a composite backend
+///
+/// A child is marked unhealthy after a number of consecutive failures. Once
+/// the cooldown passed, it is available again for a probe: if the next
+/// message succeeds it is healthy again, otherwise the cooldown restarts.
+pub struct HealthTracker {
+    pub failure_threshold: u32,
+    pub cooldown: Duration,
+    members: Mutex<Vec<MemberHealth>>,
+}
+
+#[derive(Clone, Default)]
+struct MemberHealth {
+    failures: u32,
+    down_until: Option<Instant>,
+}
+
+impl HealthTracker {
+    /// Construct a new HealthTracker for `size` healthy members
+    pub fn new(size: usize) -> HealthTracker {
+        HealthTracker {
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            cooldown: DEFAULT_COOLDOWN,
+            members: Mutex::new(vec![MemberHealth::default(); size]),
+        }
+    }
+
+    /// Return whether the member is healthy
+    pub fn is_healthy(&self, index: usize) -> bool {
+        self.members.lock().unwrap()[index].down_until.is_none()
+    }
+
+    /// Return whether the member is healthy or due for a probe
+    pub fn is_available(&self, index: usize) -> bool {
+        self.members.lock().unwrap()[index].down_until.is_none_or(|until| Instant::now() >= until)
+    }
+
+    /// Record a successful operation of the member
+    pub fn record_success(&self, index: usize) {
+        let mut members = self.members.lock().unwrap();
+        members[index] = MemberHealth::default();
+    }
+
+    /// Record a failed operation of the member
+    pub fn record_failure(&self, index: usize) {
+        let mut members = self.members.lock().unwrap();
+        let member = &mut members[index];
+
+        member.failures = member.failures.saturating_add(1);
+        if member.failures >= self.failure_threshold {
+            member.down_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    /// Log a message to the first available backend in the given order
+    ///
+    /// Failing backends are skipped and the next one is tried. If none of
+    /// the backends is available, all of them are tried. Returns the index of
+    /// the backend which accepted the message.
+    pub fn log_to_first(
+        &self,
+        backends: &[Box<dyn Backend>],
+        order: &[usize],
+        msg: WireMessage,
+    ) -> Result<usize> {
+        let mut candidates: Vec<usize> = order.iter().cloned().filter(|&index| self.is_available(index)).collect();
+        if candidates.is_empty() {
+            candidates = order.to_vec();
+        }
+
+        let mut last_error = None;
+        for index in candidates {
+            match backends[index].log_message(msg.clone()) {
+                Ok(()) => {
+                    self.record_success(index);
+                    return Ok(index);
+                }
+                Err(e) => {
+                    self.record_failure(index);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        let error = last_error.unwrap_or_else(|| format_err!("No backend configured"));
+        Err(error.context("All backends failed to log the message")
+            .context(Error::LogTransmitFailed)
+            .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mark_unhealthy_after_consecutive_failures() {
+        let mut health = HealthTracker::new(1);
+        health.failure_threshold = 2;
+        health.cooldown = Duration::from_millis(20);
+
+        health.record_failure(0);
+        health.record_success(0);
+        health.record_failure(0);
+        assert!(health.is_healthy(0));
+
+        health.record_failure(0);
+        assert!(!health.is_healthy(0));
+        assert!(!health.is_available(0));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(health.is_available(0));
+
+        // A failed probe restarts the cooldown
+        health.record_failure(0);
+        assert!(!health.is_available(0));
+
+        health.record_success(0);
+        assert!(health.is_healthy(0));
+    }
+}
@@ -0,0 +1,300 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::{Backend, Level, Message, MetadataValue, Result, WireMessage};
+
+/// MemoryBackend captures all messages in memory
+///
+/// It is meant for tests asserting on the messages an application logs. The
+/// captured messages are inspected through a `MemoryHandle`, which stays
+/// usable after the backend was moved into a `Logger`.
+///
+/// ```
+/// # use gelf::{Level, Logger, MemoryBackend, Message};
+/// let backend = MemoryBackend::new();
+/// let captured = backend.handle();
+///
+/// let logger = Logger::new_with_hostname(Box::new(backend), "localhost");
+/// logger.log_message(Message::new_with_level("Hello", Level::Warning));
+///
+/// captured.assert_logged("a warning", |msg| msg.level() == Level::Warning);
+/// ```
+#[derive(Default)]
+pub struct MemoryBackend {
+    shared: Arc<Shared>,
+}
+
+/// MemoryHandle gives access to the messages captured by a `MemoryBackend`
+#[derive(Clone)]
+pub struct MemoryHandle {
+    shared: Arc<Shared>,
+}
+
+/// CapturedMessage is a fully assembled message captured by a `MemoryBackend`
+#[derive(Clone, Debug)]
+pub struct CapturedMessage {
+    host: String,
+    message: Message<'static>,
+    json: String,
+}
+
+#[derive(Default)]
+struct Shared {
+    messages: Mutex<Vec<CapturedMessage>>,
+    logged: Condvar,
+}
+
+impl MemoryBackend {
+    /// Construct a new MemoryBackend
+    pub fn new() -> MemoryBackend {
+        Self::default()
+    }
+
+    /// Return a handle to the captured messages
+    pub fn handle(&self) -> MemoryHandle {
+        MemoryHandle {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Backend for MemoryBackend {
+    /// Capture a message.
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        let captured = CapturedMessage {
+            json: msg.to_gelf()?,
+            host: msg.host().to_string(),
+            message: msg.message().clone().into_owned(),
+        };
+
+        self.shared.messages.lock().unwrap().push(captured);
+        self.shared.logged.notify_all();
+
+        Ok(())
+    }
+}
+
+impl MemoryHandle {
+    fn lock(&self) -> MutexGuard<'_, Vec<CapturedMessage>> {
+        self.shared.messages.lock().unwrap()
+    }
+
+    /// Return all captured messages
+    pub fn messages(&self) -> Vec<CapturedMessage> {
+        self.lock().clone()
+    }
+
+    /// Return the number of captured messages
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Return whether no message was captured
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Remove all captured messages
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Return all captured messages matching the predicate
+    pub fn find<F: Fn(&CapturedMessage) -> bool>(&self, predicate: F) -> Vec<CapturedMessage> {
+        self.lock().iter().filter(|msg| predicate(msg)).cloned().collect()
+    }
+
+    /// Return all captured messages with the given level
+    pub fn find_by_level(&self, level: Level) -> Vec<CapturedMessage> {
+        self.find(|msg| msg.level() == level)
+    }
+
+    /// Return all captured messages with the given value of an additional field
+    pub fn find_by_field<'a, V: Into<MetadataValue<'a>>>(&self, key: &str, value: V) -> Vec<CapturedMessage> {
+        let value = value.into();
+        self.find(|msg| msg.metadata(key) == Some(&value))
+    }
+
+    /// Wait until at least `count` messages are captured
+    ///
+    /// Returns false if the timeout elapsed before.
+    pub fn wait_for(&self, count: usize, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut messages = self.lock();
+
+        while messages.len() < count {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+
+            messages = self.shared.logged.wait_timeout(messages, deadline - now).unwrap().0;
+        }
+
+        true
+    }
+
+    /// Assert that a message matching the predicate was captured and return it
+    ///
+    /// Panics with a listing of all captured messages otherwise.
+    pub fn assert_logged<F: Fn(&CapturedMessage) -> bool>(&self, description: &str, predicate: F) -> CapturedMessage {
+        match self.find(predicate).into_iter().next() {
+            Some(msg) => msg,
+            None => panic!("Expected {} to be logged, captured:\n{}", description, self.listing()),
+        }
+    }
+
+    /// Assert that no message matching the predicate was captured
+    ///
+    /// Panics with a listing of all captured messages otherwise.
+    pub fn assert_not_logged<F: Fn(&CapturedMessage) -> bool>(&self, description: &str, predicate: F) {
+        if !self.find(predicate).is_empty() {
+            panic!("Expected no {} to be logged, captured:\n{}", description, self.listing());
+        }
+    }
+
+    /// Assert that exactly `count` messages were captured
+    pub fn assert_count(&self, count: usize) {
+        let len = self.len();
+        if len != count {
+            panic!("Expected {} messages to be logged, captured {}:\n{}", count, len, self.listing());
+        }
+    }
+
+    /// Return the JSON of all captured messages, one per line
+    fn listing(&self) -> String {
+        let messages = self.lock();
+        if messages.is_empty() {
+            return "  (nothing)".to_string();
+        }
+
+        messages.iter().map(|msg| format!("  {}", msg.json)).collect::<Vec<_>>().join("\n")
+    }
+}
+
+impl CapturedMessage {
+    /// Return the `host` of the message
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Return the `level` of the message
+    pub fn level(&self) -> Level {
+        self.message.level()
+    }
+
+    /// Return the `short_message` of the message
+    pub fn short_message(&self) -> &str {
+        self.message.short_message()
+    }
+
+    /// Return the `full_message` of the message
+    pub fn full_message(&self) -> Option<&str> {
+        self.message.full_message().as_deref()
+    }
+
+    /// Return the value of an additional field
+    pub fn metadata(&self, key: &str) -> Option<&MetadataValue<'static>> {
+        self.message.metadata(key)
+    }
+
+    /// Return the message including all default metadata of the logger
+    pub fn message(&self) -> &Message<'static> {
+        &self.message
+    }
+
+    /// Return the message serialized to GELF/JSON
+    pub fn json(&self) -> &str {
+        &self.json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::Logger;
+
+    fn logger(backend: MemoryBackend) -> Logger {
+        let mut logger = Logger::new_with_hostname(Box::new(backend), "test-host");
+        logger.set_default_metadata("facility", "tests").unwrap();
+        logger
+    }
+
+    #[test]
+    fn capture_assembled_messages() {
+        let backend = MemoryBackend::new();
+        let captured = backend.handle();
+        let logger = logger(backend);
+
+        let mut message = Message::new_with_level("first", Level::Error);
+        message.set_metadata("request_id", 42).unwrap();
+        logger.log_message(message);
+        logger.log_message(Message::new_with_level("second", Level::Informational));
+
+        captured.assert_count(2);
+
+        let first = &captured.messages()[0];
+        assert_eq!(first.host(), "test-host");
+        assert_eq!(first.short_message(), "first");
+        assert_eq!(first.metadata("facility").unwrap(), "tests");
+        assert_eq!(first.metadata("request_id").and_then(MetadataValue::as_i64), Some(42));
+
+        let json: serde_json::Value = serde_json::from_str(first.json()).unwrap();
+        assert_eq!(json["_request_id"], 42);
+        assert_eq!(json["host"], "test-host");
+    }
+
+    #[test]
+    fn query_by_level_and_field() {
+        let backend = MemoryBackend::new();
+        let captured = backend.handle();
+        let logger = logger(backend);
+
+        for (i, level) in [Level::Error, Level::Warning, Level::Error].iter().enumerate() {
+            let mut message = Message::new_with_level(format!("message {}", i), *level);
+            message.set_metadata("index", i as i64).unwrap();
+            logger.log_message(message);
+        }
+
+        assert_eq!(captured.find_by_level(Level::Error).len(), 2);
+        assert_eq!(captured.find_by_field("index", 1).len(), 1);
+        assert_eq!(captured.find_by_field("facility", "tests").len(), 3);
+        assert!(captured.find_by_field("index", "1").is_empty());
+
+        let warning = captured.assert_logged("a warning", |msg| msg.level() == Level::Warning);
+        assert_eq!(warning.short_message(), "message 1");
+        captured.assert_not_logged("a debug message", |msg| msg.level() == Level::Debug);
+
+        captured.clear();
+        assert!(captured.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected a critical message to be logged")]
+    fn panic_with_listing_on_failed_assertion() {
+        let backend = MemoryBackend::new();
+        let captured = backend.handle();
+        logger(backend).log_message(Message::new_with_level("not critical", Level::Notice));
+
+        captured.assert_logged("a critical message", |msg| msg.level() == Level::Critical);
+    }
+
+    #[test]
+    fn wait_for_messages_from_other_threads() {
+        let backend = MemoryBackend::new();
+        let captured = backend.handle();
+        let logger = logger(backend);
+
+        assert!(!captured.wait_for(1, Duration::from_millis(10)));
+
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            logger.log_message(Message::new("first"));
+            logger.log_message(Message::new("second"));
+        });
+
+        assert!(captured.wait_for(2, Duration::from_secs(5)));
+        sender.join().unwrap();
+    }
+}
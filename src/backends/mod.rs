@@ -1,10 +1,41 @@
+mod asynchronous;
+mod backoff;
+mod balanced;
+mod failover;
+mod fanout;
+mod file;
+mod health;
+mod http;
+mod memory;
 mod null;
+mod size_routing;
+mod spool;
+mod stream;
 mod tcp;
+#[cfg(test)]
+mod test_util;
+#[cfg(feature = "tls")]
+mod tls;
 mod udp;
+#[cfg(unix)]
+mod unix;
 
+pub use self::asynchronous::{AsyncBackend, OverflowPolicy};
+pub use self::balanced::{BalanceStrategy, BalancedBackend};
+pub use self::failover::FailoverBackend;
+pub use self::fanout::FanoutBackend;
+pub use self::file::{FileBackend, FileFraming, SyncPolicy};
+pub use self::http::HttpBackend;
+pub use self::memory::{CapturedMessage, MemoryBackend, MemoryHandle};
 pub use self::null::NullBackend;
+pub use self::size_routing::SizeRoutingBackend;
+pub use self::spool::SpoolingBackend;
 pub use self::tcp::TcpBackend;
-pub use self::udp::UdpBackend;
+#[cfg(feature = "tls")]
+pub use self::tls::TlsConfig;
+pub use self::udp::{IpPreference, Pacing, UdpBackend, UdpBackendBuilder};
+#[cfg(unix)]
+pub use self::unix::{UnixDatagramBackend, UnixStreamBackend};
 
 use crate::{WireMessage, Result};
 
@@ -16,5 +47,12 @@ use crate::{WireMessage, Result};
 /// `ChunkedMessage` or compression with `MessageCompression`)
 pub trait Backend: Sync + Send {
     /// Log a message.
-   fn log_message(&self, msg: WireMessage) -> Result<()>;
+    fn log_message(&self, msg: WireMessage) -> Result<()>;
+
+    /// Flush all messages buffered by the backend.
+    ///
+    /// Backends which send every message immediately don't need to implement this.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
 }
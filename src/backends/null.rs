@@ -1,6 +1,7 @@
 use crate::{Backend, WireMessage, Result};
 
 /// The `NullBackend` is a utility backend which discards all messages
+#[derive(Default)]
 pub struct NullBackend;
 
 impl NullBackend {
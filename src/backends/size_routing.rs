@@ -0,0 +1,148 @@
+use crate::{Backend, ChunkSize, Error, MessageCompression, Result, WireMessage};
+
+/// Maximum number of chunks of a GELF message
+const MAX_CHUNKS: u8 = 128;
+
+/// SizeRoutingBackend sends small messages and large messages to different backends
+///
+/// Typically small messages go to a `UdpBackend` and large ones to a
+/// `TcpBackend` or `HttpBackend`, since UDP can't transport messages of more
+/// than 128 chunks. The compressed payload is measured once; it is cached in
+/// the message, so a backend using the same compression doesn't compress the
+/// message again.
+///
+/// By default every message which fits 128 chunks of `ChunkSize::LAN` is
+/// small. The compression and chunk budget should match the small backend.
+///
+/// ```no_run
+/// # use gelf::{ChunkSize, SizeRoutingBackend, TcpBackend, UdpBackend};
+/// let udp = UdpBackend::new_with_chunksize("graylog.example.com:12201", ChunkSize::WAN).unwrap();
+/// let tcp = TcpBackend::new("graylog.example.com:12201").unwrap();
+///
+/// let mut backend = SizeRoutingBackend::new(Box::new(udp), Box::new(tcp));
+/// backend.set_chunk_budget(ChunkSize::WAN, 1).unwrap();
+/// ```
+pub struct SizeRoutingBackend {
+    small: Box<dyn Backend>,
+    large: Box<dyn Backend>,
+    compression: MessageCompression,
+    max_size: usize,
+}
+
+impl SizeRoutingBackend {
+    /// Construct a new SizeRoutingBackend
+    pub fn new(small: Box<dyn Backend>, large: Box<dyn Backend>) -> SizeRoutingBackend {
+        SizeRoutingBackend {
+            small,
+            large,
+            compression: MessageCompression::default(),
+            max_size: ChunkSize::LAN.size() as usize * MAX_CHUNKS as usize,
+        }
+    }
+
+    /// Return the compression used to measure messages
+    pub fn compression(&self) -> MessageCompression {
+        self.compression
+    }
+
+    /// Set the compression used to measure messages
+    pub fn set_compression(&mut self, compression: MessageCompression) -> &mut Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Return the maximum compressed size of small messages in bytes
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Set the maximum compressed size of small messages in bytes
+    pub fn set_max_size(&mut self, max_size: usize) -> &mut Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Set the maximum size of small messages as a number of chunks
+    ///
+    /// With a single chunk, small messages are sent as one datagram.
+    pub fn set_chunk_budget(&mut self, chunk_size: ChunkSize, max_chunks: u8) -> Result<&mut Self> {
+        if max_chunks == 0 || max_chunks > MAX_CHUNKS {
+            return Err(format_err!("The chunk budget must be between 1 and {}", MAX_CHUNKS)
+                .context(Error::ChunkMessageFailed)
+                .into());
+        }
+
+        self.max_size = chunk_size.size() as usize * max_chunks as usize;
+        Ok(self)
+    }
+}
+
+impl Backend for SizeRoutingBackend {
+    /// Log a message to the backend matching its size
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        if msg.to_compressed_gelf(self.compression)?.len() <= self.max_size {
+            self.small.log_message(msg)
+        } else {
+            self.large.log_message(msg)
+        }
+    }
+
+    /// Flush both backends
+    fn flush(&self) -> Result<()> {
+        let small = self.small.flush();
+        let large = self.large.flush();
+
+        small.and(large)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryBackend;
+    use crate::backends::test_util::log;
+
+    #[test]
+    fn route_by_compressed_size() {
+        let (small, large) = (MemoryBackend::new(), MemoryBackend::new());
+        let (small_messages, large_messages) = (small.handle(), large.handle());
+
+        let mut backend = SizeRoutingBackend::new(Box::new(small), Box::new(large));
+        backend
+            .set_compression(MessageCompression::None)
+            .set_chunk_budget(ChunkSize::Custom(200), 1)
+            .unwrap();
+        assert_eq!(backend.max_size(), 200);
+
+        log(&backend, "short").unwrap();
+        log(&backend, &"long ".repeat(50)).unwrap();
+
+        small_messages.assert_logged("the short message", |msg| msg.short_message() == "short");
+        large_messages.assert_logged("the long message", |msg| msg.short_message().starts_with("long"));
+        small_messages.assert_count(1);
+        large_messages.assert_count(1);
+    }
+
+    #[test]
+    fn measure_compressed_size() {
+        let (small, large) = (MemoryBackend::new(), MemoryBackend::new());
+        let (small_messages, large_messages) = (small.handle(), large.handle());
+
+        // The repetitive message compresses well below the limit
+        let mut backend = SizeRoutingBackend::new(Box::new(small), Box::new(large));
+        backend.set_max_size(200);
+        log(&backend, &"long ".repeat(100)).unwrap();
+
+        small_messages.assert_count(1);
+        large_messages.assert_count(0);
+    }
+
+    #[test]
+    fn reject_invalid_chunk_budget() {
+        let mut backend = SizeRoutingBackend::new(Box::new(MemoryBackend::new()), Box::new(MemoryBackend::new()));
+
+        assert!(backend.set_chunk_budget(ChunkSize::WAN, 0).is_err());
+        assert!(backend.set_chunk_budget(ChunkSize::WAN, 129).is_err());
+        assert!(backend.set_chunk_budget(ChunkSize::WAN, 128).is_ok());
+    }
+}
@@ -0,0 +1,593 @@
+use failure::Fail;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{Backend, Error, Result, WireMessage};
+
+/// Default maximum size of all segment files in bytes
+const DEFAULT_MAX_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Default size after which a new segment file is started
+const DEFAULT_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Default minimum time between replay attempts after a failure
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// File extension of segment files
+const SEGMENT_EXTENSION: &str = "seg";
+
+/// Name of the file holding the replay position
+const POSITION_FILE: &str = "position";
+
+/// Size of the length prefix of every record
+const RECORD_HEADER_SIZE: u64 = 4;
+
+/// SpoolingBackend persists messages its inner backend fails to send
+///
+/// Whenever the inner backend fails, the serialized message is appended to
+/// an on-disk queue in the spool directory. The queue consists of segment
+/// files with length-prefixed records and a file tracking the replay
+/// position, so it survives restarts of the process.
+///
+/// Spooled messages are replayed in order before any new message is sent.
+/// Replays are attempted with new messages (at most once per retry
+/// interval), on `flush` and on explicit calls to `replay`. While the queue
+/// is not empty, new messages are appended to it to preserve their order.
+///
+/// If the queue would exceed its maximum size, the newest message is dropped.
+pub struct SpoolingBackend {
+    inner: Box<dyn Backend>,
+    retry_interval: Duration,
+    spool: Mutex<Spool>,
+}
+
+/// A bounded on-disk queue of records
+struct Spool {
+    directory: PathBuf,
+    segments: VecDeque<Segment>,
+    next_id: u64,
+    writer: Option<File>,
+    reader: Option<BufReader<File>>,
+    peeked: Option<Vec<u8>>,
+    offset: u64,
+    position: File,
+    queued: u64,
+    size: u64,
+    dropped: u64,
+    max_size: u64,
+    segment_size: u64,
+    next_retry: Instant,
+}
+
+/// A segment file of the spool
+struct Segment {
+    id: u64,
+    size: u64,
+}
+
+impl SpoolingBackend {
+    /// Construct a new SpoolingBackend spooling to the given directory
+    ///
+    /// The directory is created if necessary. Messages left in the directory
+    /// by a previous process are replayed first.
+    pub fn new<P: AsRef<Path>>(inner: Box<dyn Backend>, directory: P) -> Result<SpoolingBackend> {
+        let spool = Spool::open(directory.as_ref()).map_err(|e| {
+            e.context(format!("Failed to open spool directory '{}'", directory.as_ref().display()))
+                .context(Error::BackendCreationFailed)
+        })?;
+
+        Ok(SpoolingBackend {
+            inner,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            spool: Mutex::new(spool),
+        })
+    }
+
+    /// Return the maximum size of the spool in bytes
+    pub fn max_size(&self) -> u64 {
+        self.spool.lock().unwrap().max_size
+    }
+
+    /// Set the maximum size of the spool in bytes
+    pub fn set_max_size(&mut self, size: u64) -> &mut Self {
+        self.spool.get_mut().unwrap().max_size = size;
+        self
+    }
+
+    /// Return the size after which a new segment file is started
+    pub fn segment_size(&self) -> u64 {
+        self.spool.lock().unwrap().segment_size
+    }
+
+    /// Set the size after which a new segment file is started
+    ///
+    /// Segment files are deleted once all their messages are replayed.
+    pub fn set_segment_size(&mut self, size: u64) -> &mut Self {
+        self.spool.get_mut().unwrap().segment_size = size;
+        self
+    }
+
+    /// Return the minimum time between replay attempts after a failure
+    pub fn retry_interval(&self) -> Duration {
+        self.retry_interval
+    }
+
+    /// Set the minimum time between replay attempts after a failure
+    pub fn set_retry_interval(&mut self, interval: Duration) -> &mut Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// Return the number of messages waiting in the spool
+    pub fn spooled_messages(&self) -> u64 {
+        self.spool.lock().unwrap().queued
+    }
+
+    /// Return the size of all segment files in bytes
+    pub fn spooled_bytes(&self) -> u64 {
+        self.spool.lock().unwrap().size
+    }
+
+    /// Return the number of messages dropped due to a full spool
+    ///
+    /// This includes spooled messages which couldn't be parsed on replay.
+    pub fn dropped_messages(&self) -> u64 {
+        self.spool.lock().unwrap().dropped
+    }
+
+    /// Send all spooled messages to the inner backend
+    ///
+    /// Returns the number of replayed messages. Fails as soon as the inner backend fails.
+    pub fn replay(&self) -> Result<u64> {
+        let mut spool = self.spool.lock().unwrap();
+        self.replay_spool(&mut spool)
+    }
+
+    fn replay_spool(&self, spool: &mut Spool) -> Result<u64> {
+        let mut replayed = 0;
+
+        loop {
+            let msg = match spool.peek().map_err(spool_error)? {
+                Some(record) => str::from_utf8(record).ok().and_then(|json| WireMessage::from_gelf(json).ok()),
+                None => return Ok(replayed),
+            };
+
+            match msg {
+                Some(msg) => {
+                    if let Err(e) = self.inner.log_message(msg) {
+                        spool.next_retry = Instant::now() + self.retry_interval;
+                        return Err(e.context("Failed to replay spooled messages")
+                            .context(Error::LogTransmitFailed)
+                            .into());
+                    }
+                    replayed += 1;
+                }
+                // A corrupted record would block the spool forever
+                None => spool.dropped += 1,
+            }
+
+            spool.pop().map_err(spool_error)?;
+        }
+    }
+}
+
+impl Backend for SpoolingBackend {
+    /// Send a message to the inner backend or spool it on failure
+    ///
+    /// Only fails if the message had to be dropped.
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        let json = msg.to_gelf()?;
+        let mut spool = self.spool.lock().unwrap();
+
+        if spool.queued > 0 && Instant::now() >= spool.next_retry {
+            self.replay_spool(&mut spool).ok();
+        }
+
+        if spool.queued == 0 {
+            match self.inner.log_message(msg) {
+                Ok(()) => return Ok(()),
+                Err(_) => spool.next_retry = Instant::now() + self.retry_interval,
+            }
+        }
+
+        if !spool.push(json.as_bytes()).map_err(spool_error)? {
+            return Err(format_err!("The spool is full, dropped the message")
+                .context(Error::LogTransmitFailed)
+                .into());
+        }
+
+        Ok(())
+    }
+
+    /// Replay all spooled messages and flush the inner backend
+    fn flush(&self) -> Result<()> {
+        self.replay()?;
+        self.inner.flush()
+    }
+}
+
+impl Spool {
+    /// Open the spool in the given directory and recover its state
+    fn open(directory: &Path) -> io::Result<Spool> {
+        fs::create_dir_all(directory)?;
+
+        let mut ids: Vec<u64> = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+
+            if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok()) {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+
+        let mut position = OpenOptions::new().read(true).write(true).create(true).truncate(false)
+            .open(directory.join(POSITION_FILE))?;
+        let mut content = String::new();
+        position.read_to_string(&mut content)?;
+
+        let mut parts = content.split_whitespace().map(|part| part.parse::<u64>().ok());
+        let (position_id, mut offset) = match (parts.next().flatten(), parts.next().flatten()) {
+            (Some(id), Some(offset)) => (id, offset),
+            _ => (0, 0),
+        };
+
+        let mut spool = Spool {
+            directory: directory.to_path_buf(),
+            segments: VecDeque::new(),
+            next_id: position_id,
+            writer: None,
+            reader: None,
+            peeked: None,
+            offset: 0,
+            position,
+            queued: 0,
+            size: 0,
+            dropped: 0,
+            max_size: DEFAULT_MAX_SIZE,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            next_retry: Instant::now(),
+        };
+
+        for id in ids {
+            // Segments before the replay position are fully replayed
+            if id < position_id {
+                fs::remove_file(spool.segment_path(id))?;
+                continue;
+            }
+
+            if id != position_id || !spool.segments.is_empty() {
+                offset = 0;
+            }
+
+            let (records, size) = spool.recover_segment(id, offset)?;
+            if spool.segments.is_empty() {
+                spool.offset = offset.min(size);
+            }
+
+            spool.segments.push_back(Segment { id, size });
+            spool.queued += records;
+            spool.size += size;
+            spool.next_id = id + 1;
+        }
+
+        Ok(spool)
+    }
+
+    /// Count the complete records of a segment and cut off an incomplete one
+    fn recover_segment(&self, id: u64, offset: u64) -> io::Result<(u64, u64)> {
+        let file = OpenOptions::new().read(true).write(true).open(self.segment_path(id))?;
+        let file_size = file.metadata()?.len();
+
+        let mut reader = BufReader::new(&file);
+        let mut valid = 0;
+        let mut records = 0;
+
+        loop {
+            let mut header = [0; RECORD_HEADER_SIZE as usize];
+            if reader.read_exact(&mut header).is_err() {
+                break;
+            }
+
+            let end = valid + RECORD_HEADER_SIZE + u64::from(u32::from_le_bytes(header));
+            if end > file_size {
+                break;
+            }
+
+            reader.seek_relative(end as i64 - valid as i64 - RECORD_HEADER_SIZE as i64)?;
+            valid = end;
+            if valid > offset {
+                records += 1;
+            }
+        }
+
+        if valid < file_size {
+            file.set_len(valid)?;
+        }
+
+        Ok((records, valid))
+    }
+
+    fn segment_path(&self, id: u64) -> PathBuf {
+        self.directory.join(format!("{:020}.{}", id, SEGMENT_EXTENSION))
+    }
+
+    /// Append a record, returns false if the spool is full
+    fn push(&mut self, record: &[u8]) -> io::Result<bool> {
+        let len = RECORD_HEADER_SIZE + record.len() as u64;
+        if self.size + len > self.max_size {
+            self.dropped += 1;
+            return Ok(false);
+        }
+
+        let roll = match self.segments.back() {
+            Some(back) => back.size > 0 && back.size + len > self.segment_size,
+            None => true,
+        };
+
+        if roll {
+            let id = self.next_id;
+            self.writer = Some(File::create(self.segment_path(id))?);
+            self.segments.push_back(Segment { id, size: 0 });
+            self.next_id += 1;
+        }
+
+        if self.writer.is_none() {
+            let id = self.segments.back().expect("Should have a segment").id;
+            self.writer = Some(OpenOptions::new().append(true).open(self.segment_path(id))?);
+        }
+
+        let mut data = Vec::with_capacity(len as usize);
+        data.extend_from_slice(&(record.len() as u32).to_le_bytes());
+        data.extend_from_slice(record);
+
+        let back = self.segments.back_mut().expect("Should have a segment");
+        let writer = self.writer.as_mut().expect("Should have a writer");
+
+        if let Err(e) = writer.write_all(&data) {
+            // Don't leave a partial record behind
+            writer.set_len(back.size).ok();
+            self.writer = None;
+            return Err(e);
+        }
+
+        back.size += len;
+        self.size += len;
+        self.queued += 1;
+
+        Ok(true)
+    }
+
+    /// Return the oldest record without removing it
+    fn peek(&mut self) -> io::Result<Option<&[u8]>> {
+        if self.queued == 0 {
+            return Ok(None);
+        }
+
+        if self.peeked.is_none() {
+            if self.reader.is_none() {
+                let id = self.segments.front().expect("Should have a segment").id;
+                let mut file = File::open(self.segment_path(id))?;
+                file.seek(SeekFrom::Start(self.offset))?;
+                self.reader = Some(BufReader::new(file));
+            }
+
+            let reader = self.reader.as_mut().expect("Should have a reader");
+            let mut header = [0; RECORD_HEADER_SIZE as usize];
+            reader.read_exact(&mut header)?;
+
+            let mut record = vec![0; u32::from_le_bytes(header) as usize];
+            reader.read_exact(&mut record)?;
+            self.peeked = Some(record);
+        }
+
+        Ok(self.peeked.as_deref())
+    }
+
+    /// Remove the oldest record and persist the new position
+    fn pop(&mut self) -> io::Result<()> {
+        let record = match self.peeked.take() {
+            Some(record) => record,
+            None => return Ok(()),
+        };
+
+        self.offset += RECORD_HEADER_SIZE + record.len() as u64;
+        self.queued -= 1;
+
+        let front = self.segments.front().expect("Should have a segment");
+        if self.offset < front.size {
+            return self.write_position(front.id);
+        }
+
+        // The front segment is completely replayed
+        let front = self.segments.pop_front().expect("Should have a segment");
+        self.size -= front.size;
+        self.offset = 0;
+        self.reader = None;
+        if self.segments.is_empty() {
+            self.writer = None;
+        }
+
+        let next_id = self.segments.front().map_or(self.next_id, |segment| segment.id);
+        self.write_position(next_id)?;
+        fs::remove_file(self.segment_path(front.id))
+    }
+
+    fn write_position(&mut self, id: u64) -> io::Result<()> {
+        let position = format!("{} {}", id, self.offset);
+
+        self.position.set_len(0)?;
+        self.position.seek(SeekFrom::Start(0))?;
+        self.position.write_all(position.as_bytes())
+    }
+}
+
+fn spool_error(e: io::Error) -> failure::Error {
+    e.context("Failed to access the spool")
+        .context(Error::LogTransmitFailed)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::test_util::{log, RecordingBackend};
+
+    fn segment_files(directory: &Path) -> usize {
+        fs::read_dir(directory).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().and_then(|e| e.to_str()) == Some(SEGMENT_EXTENSION))
+            .count()
+    }
+
+    #[test]
+    fn spool_failed_messages_and_replay_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let inner = RecordingBackend::default();
+
+        let mut backend = SpoolingBackend::new(Box::new(inner.clone()), dir.path()).unwrap();
+        backend.set_retry_interval(Duration::from_secs(0));
+
+        log(&backend, "1").unwrap();
+        inner.set_failing(true);
+        log(&backend, "2").unwrap();
+        log(&backend, "3").unwrap();
+        assert_eq!(backend.spooled_messages(), 2);
+
+        inner.set_failing(false);
+        log(&backend, "4").unwrap();
+
+        assert_eq!(inner.sent(), vec!["1", "2", "3", "4"]);
+        assert_eq!(backend.spooled_messages(), 0);
+        assert_eq!(backend.spooled_bytes(), 0);
+        assert_eq!(segment_files(dir.path()), 0);
+    }
+
+    #[test]
+    fn keep_order_until_retry_interval_passed() {
+        let dir = tempfile::tempdir().unwrap();
+        let inner = RecordingBackend::default();
+
+        let mut backend = SpoolingBackend::new(Box::new(inner.clone()), dir.path()).unwrap();
+        backend.set_retry_interval(Duration::from_secs(60));
+
+        inner.set_failing(true);
+        log(&backend, "1").unwrap();
+        inner.set_failing(false);
+        log(&backend, "2").unwrap();
+
+        assert!(inner.sent().is_empty());
+        assert_eq!(backend.spooled_messages(), 2);
+
+        backend.flush().unwrap();
+        assert_eq!(inner.sent(), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn replay_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let inner = RecordingBackend::default();
+        inner.set_failing(true);
+
+        let backend = SpoolingBackend::new(Box::new(inner.clone()), dir.path()).unwrap();
+        for i in 1..=3 {
+            log(&backend, &i.to_string()).unwrap();
+        }
+        inner.set_failing(false);
+        drop(backend);
+
+        // Replay only the first message, then restart again
+        let backend = SpoolingBackend::new(Box::new(inner.clone()), dir.path()).unwrap();
+        assert_eq!(backend.spooled_messages(), 3);
+        {
+            let mut spool = backend.spool.lock().unwrap();
+            spool.peek().unwrap();
+            spool.pop().unwrap();
+        }
+        drop(backend);
+
+        let backend = SpoolingBackend::new(Box::new(inner.clone()), dir.path()).unwrap();
+        assert_eq!(backend.spooled_messages(), 2);
+        assert_eq!(backend.replay().unwrap(), 2);
+        assert_eq!(inner.sent(), vec!["2", "3"]);
+        assert_eq!(segment_files(dir.path()), 0);
+    }
+
+    #[test]
+    fn roll_over_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let inner = RecordingBackend::default();
+        inner.set_failing(true);
+
+        let mut backend = SpoolingBackend::new(Box::new(inner.clone()), dir.path()).unwrap();
+        backend.set_segment_size(1);
+        for i in 1..=5 {
+            log(&backend, &i.to_string()).unwrap();
+        }
+        assert_eq!(segment_files(dir.path()), 5);
+
+        inner.set_failing(false);
+        assert_eq!(backend.replay().unwrap(), 5);
+        assert_eq!(inner.sent(), vec!["1", "2", "3", "4", "5"]);
+        assert_eq!(segment_files(dir.path()), 0);
+
+        // Spooling continues with a fresh segment
+        inner.set_failing(true);
+        log(&backend, "6").unwrap();
+        assert_eq!(segment_files(dir.path()), 1);
+    }
+
+    #[test]
+    fn drop_newest_messages_when_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let inner = RecordingBackend::default();
+        inner.set_failing(true);
+
+        let mut backend = SpoolingBackend::new(Box::new(inner.clone()), dir.path()).unwrap();
+        log(&backend, "1").unwrap();
+        let record_size = backend.spooled_bytes();
+        backend.set_max_size(record_size * 2);
+
+        log(&backend, "2").unwrap();
+        assert!(log(&backend, "3").is_err());
+        assert_eq!(backend.dropped_messages(), 1);
+
+        inner.set_failing(false);
+        backend.flush().unwrap();
+        assert_eq!(inner.sent(), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn cut_off_incomplete_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let inner = RecordingBackend::default();
+        inner.set_failing(true);
+
+        let backend = SpoolingBackend::new(Box::new(inner.clone()), dir.path()).unwrap();
+        log(&backend, "1").unwrap();
+        drop(backend);
+
+        // Simulate a crash while appending a record
+        let segment = fs::read_dir(dir.path()).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().and_then(|e| e.to_str()) == Some(SEGMENT_EXTENSION))
+            .unwrap();
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(&[100, 0, 0, 0, b'{']).unwrap();
+
+        let backend = SpoolingBackend::new(Box::new(inner.clone()), dir.path()).unwrap();
+        assert_eq!(backend.spooled_messages(), 1);
+        log(&backend, "2").unwrap();
+
+        inner.set_failing(false);
+        assert_eq!(backend.replay().unwrap(), 2);
+        assert_eq!(inner.sent(), vec!["1", "2"]);
+    }
+}
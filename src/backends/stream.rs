@@ -0,0 +1,555 @@
+use failure::Fail;
+use std::collections::VecDeque;
+use std::io::{self, IoSlice, Read, Write};
+use std::net;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{Result, Error};
+use crate::backends::backoff::Backoff;
+
+/// Default timeout for establishing a connection
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default minimum delay between reconnect attempts
+const DEFAULT_RECONNECT_DELAY_MIN: Duration = Duration::from_millis(100);
+
+/// Default maximum delay between reconnect attempts
+const DEFAULT_RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
+/// Default size of the outgoing buffer in bytes
+const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Default time `flush` and drop wait for the outgoing buffer to drain
+const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval in which pending bytes are retried while the socket is not writable
+const WRITE_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Maximum number of frames coalesced into a single write
+const MAX_FRAMES_PER_WRITE: usize = 64;
+
+/// A connected, non-blocking byte stream GELF frames are written to
+pub trait Stream: Read + Write + Send {
+    /// Close the stream gracefully
+    fn shutdown(&mut self) -> io::Result<()>;
+}
+
+impl Stream for net::TcpStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        net::TcpStream::shutdown(self, net::Shutdown::Both)
+    }
+}
+
+/// A Connector establishes the streams used by a `StreamSender`
+pub trait Connector: Send {
+    /// Connect to the destination and return a non-blocking stream
+    fn connect(&self, timeout: Duration) -> io::Result<Box<dyn Stream>>;
+}
+
+/// StreamSender writes frames to a stream-based connection
+///
+/// This is the transport shared by all stream-based backends (e.g. `TcpBackend`).
+///
+/// Frames are appended to a bounded outgoing buffer and written to the
+/// non-blocking stream with as few (vectored) writes as possible. Partially
+/// written frames are tracked, so frames never interleave or get truncated.
+/// Whatever the stream doesn't accept immediately is written by a background
+/// writer thread, or by an explicit `flush`.
+///
+/// A broken connection is detected on the next write. The background writer
+/// then reconnects with an exponential backoff (with jitter), so logging never
+/// waits for a connection attempt. Frames stay buffered while disconnected and
+/// are sent once the connection is re-established. If the buffer overflows,
+/// the oldest frames are dropped.
+pub struct StreamSender {
+    shared: Arc<Shared>,
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+/// State shared between the StreamSender and its background writer
+struct Shared {
+    connector: Mutex<Box<dyn Connector>>,
+    connection: Mutex<Connection>,
+    pending: Condvar,
+    drained: Condvar,
+    shutdown: AtomicBool,
+}
+
+/// The state of a StreamSender's connection
+pub struct Connection {
+    stream: Option<Box<dyn Stream>>,
+    unflushed: bool,
+    pub connect_timeout: Duration,
+    pub flush_timeout: Duration,
+    pub backoff: Backoff,
+    next_attempt: Instant,
+    buffer: VecDeque<Vec<u8>>,
+    buffer_len: usize,
+    pub buffer_size: usize,
+    offset: usize,
+    dropped: u64,
+}
+
+impl StreamSender {
+    /// Construct a new StreamSender and establish the initial connection
+    pub fn new(connector: Box<dyn Connector>, name: &str) -> Result<StreamSender> {
+        let mut connection = Connection {
+            stream: None,
+            unflushed: false,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            flush_timeout: DEFAULT_FLUSH_TIMEOUT,
+            backoff: Backoff::new(DEFAULT_RECONNECT_DELAY_MIN, DEFAULT_RECONNECT_DELAY_MAX),
+            next_attempt: Instant::now(),
+            buffer: VecDeque::new(),
+            buffer_len: 0,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            offset: 0,
+            dropped: 0,
+        };
+
+        let stream = connector.connect(connection.connect_timeout);
+        connection.connected(stream).map_err(|e| {
+            failure::Error::from(e)
+                .context("Failed to establish connection")
+                .context(Error::BackendCreationFailed)
+        })?;
+
+        let shared = Arc::new(Shared {
+            connector: Mutex::new(connector),
+            connection: Mutex::new(connection),
+            pending: Condvar::new(),
+            drained: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+
+        let writer_shared = shared.clone();
+        let writer = thread::Builder::new()
+            .name(format!("gelf-{}-writer", name))
+            .spawn(move || writer_shared.run_writer())
+            .map_err(|e| {
+                e.context("Failed to spawn the background writer thread")
+                    .context(Error::BackendCreationFailed)
+            })?;
+
+        Ok(StreamSender {
+            shared,
+            writer: Some(writer),
+        })
+    }
+
+    /// Return the locked connection state, e.g. for changing its settings
+    pub fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.shared.connection.lock().unwrap()
+    }
+
+    /// Append a frame to the outgoing buffer and write as much as possible
+    ///
+    /// This never reconnects, which is left to the background writer. It only
+    /// fails if the frame had to be dropped.
+    pub fn send(&self, frame: Vec<u8>) -> Result<()> {
+        let mut connection = self.connection();
+
+        connection.enqueue(frame)?;
+        connection.write_buffered();
+
+        if !connection.is_drained() {
+            self.shared.pending.notify_one();
+        }
+
+        Ok(())
+    }
+
+    /// Wait until all buffered frames are written to the stream
+    ///
+    /// Fails if the buffer can't be drained within the flush timeout.
+    pub fn flush(&self) -> Result<()> {
+        let connection = self.connection();
+        let timeout = connection.flush_timeout;
+
+        self.shared.flush(connection, timeout)
+    }
+}
+
+impl Drop for StreamSender {
+    /// Let the writer send all buffered frames and close the connection gracefully
+    fn drop(&mut self) {
+        // Signal under the lock, so the wakeup can't get lost between the
+        // writer's shutdown check and its wait
+        {
+            let _connection = self.shared.connection.lock().unwrap();
+            self.shared.shutdown.store(true, Ordering::SeqCst);
+            self.shared.pending.notify_all();
+        }
+
+        if let Some(writer) = self.writer.take() {
+            writer.join().unwrap_or_else(|_| warn!("The GELF writer thread panicked"));
+        }
+
+        // When drop() is called unwrap() should never fail
+        let mut connection = self.shared.connection.lock().unwrap();
+
+        if let Some(mut stream) = connection.stream.take() {
+            stream
+                .flush()
+                .and_then(|_| stream.shutdown())
+                .unwrap_or_else(|_| warn!("Failed to flush and shutdown the socket cleanly"));
+        }
+    }
+}
+
+impl Shared {
+    /// Write pending bytes whenever the stream (or a new connection) allows it
+    ///
+    /// On shutdown the remaining frames are written within the flush timeout.
+    fn run_writer(&self) {
+        let mut connection = self.connection.lock().unwrap();
+
+        while !self.shutdown.load(Ordering::SeqCst) {
+            if connection.is_drained() {
+                connection = self.pending.wait(connection).unwrap();
+                continue;
+            }
+
+            connection = self.write_pending(connection);
+
+            if connection.is_drained() {
+                self.drained.notify_all();
+            } else {
+                let delay = connection.retry_delay();
+                connection = self.pending.wait_timeout(connection, delay).unwrap().0;
+            }
+        }
+
+        let deadline = Instant::now() + connection.flush_timeout;
+
+        loop {
+            connection = self.write_pending(connection);
+
+            let now = Instant::now();
+            if connection.is_drained() {
+                return;
+            } else if now >= deadline {
+                warn!("Failed to flush all buffered GELF messages");
+                return;
+            }
+
+            let delay = connection.retry_delay().min(deadline - now);
+            connection = self.pending.wait_timeout(connection, delay).unwrap().0;
+        }
+    }
+
+    /// Reconnect if necessary and write as many buffered bytes as possible
+    ///
+    /// The connection is unlocked while connecting, so logging threads are
+    /// not blocked by the connect timeout.
+    fn write_pending<'a>(&'a self, mut connection: MutexGuard<'a, Connection>) -> MutexGuard<'a, Connection> {
+        if connection.reconnect_due() {
+            let timeout = connection.connect_timeout;
+            drop(connection);
+
+            let stream = self.connector.lock().unwrap().connect(timeout);
+
+            connection = self.connection.lock().unwrap();
+            if let Err(e) = connection.connected(stream) {
+                debug!("Failed to reconnect GELF backend: {}", e);
+            }
+        }
+
+        connection.write_buffered();
+        connection
+    }
+
+    /// Wait up to `timeout` for the outgoing buffer to drain
+    ///
+    /// Reconnecting is left to the background writer.
+    fn flush(&self, mut connection: MutexGuard<'_, Connection>, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            connection.write_buffered();
+            if !connection.is_drained() {
+                self.pending.notify_one();
+            }
+
+            if connection.is_drained() {
+                return Ok(());
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(format_err!("Timed out flushing {} buffered bytes", connection.buffered_bytes())
+                    .context(Error::LogTransmitFailed)
+                    .into());
+            }
+
+            let delay = connection.retry_delay().min(deadline - now);
+            connection = self.drained.wait_timeout(connection, delay).unwrap().0;
+        }
+    }
+}
+
+impl Connection {
+    /// Return whether a connection is currently established
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Return the number of bytes waiting in the outgoing buffer
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffer_len - self.offset
+    }
+
+    /// Return the number of frames waiting in the outgoing buffer
+    #[cfg(test)]
+    pub fn buffered_frames(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Return the number of frames dropped due to a full outgoing buffer
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Return whether every frame was handed to the stream and the stream flushed
+    fn is_drained(&self) -> bool {
+        self.buffer.is_empty() && !self.unflushed
+    }
+
+    /// Use the result of a connection attempt
+    ///
+    /// A failed attempt schedules the next one according to the backoff.
+    fn connected(&mut self, stream: io::Result<Box<dyn Stream>>) -> io::Result<()> {
+        match stream {
+            Ok(stream) => {
+                self.stream = Some(stream);
+                self.backoff.reset();
+                Ok(())
+            }
+            Err(e) => {
+                self.next_attempt = Instant::now() + self.backoff.next_delay();
+                Err(e)
+            }
+        }
+    }
+
+    /// Return whether the connection is down and the backoff allows a new attempt
+    fn reconnect_due(&mut self) -> bool {
+        if !self.is_alive() {
+            self.disconnect();
+        }
+
+        self.stream.is_none() && Instant::now() >= self.next_attempt
+    }
+
+    /// Drop a broken connection
+    ///
+    /// A partially written frame is incomplete on the old connection and
+    /// therefore resent completely on the next one.
+    fn disconnect(&mut self) {
+        if self.stream.take().is_some() {
+            self.next_attempt = Instant::now() + self.backoff.next_delay();
+        }
+
+        self.offset = 0;
+        self.unflushed = false;
+    }
+
+    /// Check whether the peer closed the connection
+    ///
+    /// A GELF server never sends data, so a read returns either `WouldBlock` on a
+    /// healthy connection or EOF (or an error) on a closed one.
+    fn is_alive(&mut self) -> bool {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return false,
+        };
+
+        let mut buf = [0; 64];
+        match stream.read(&mut buf) {
+            Ok(0) => false,
+            Ok(_) => true,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => true,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => true,
+            Err(_) => false,
+        }
+    }
+
+    /// Return how long to wait before trying to write pending bytes again
+    fn retry_delay(&self) -> Duration {
+        if self.stream.is_some() {
+            WRITE_RETRY_INTERVAL
+        } else {
+            self.next_attempt
+                .saturating_duration_since(Instant::now())
+                .max(WRITE_RETRY_INTERVAL)
+        }
+    }
+
+    /// Append a frame to the outgoing buffer, dropping the oldest frames on overflow
+    ///
+    /// A partially written frame is never dropped, since that would corrupt the stream.
+    fn enqueue(&mut self, frame: Vec<u8>) -> Result<()> {
+        let pinned = if self.offset > 0 { self.buffer[0].len() } else { 0 };
+
+        if frame.len() > self.buffer_size.saturating_sub(pinned) {
+            self.dropped += 1;
+            return Err(format_err!("Message exceeds the outgoing buffer size")
+                .context(Error::LogTransmitFailed)
+                .into());
+        }
+
+        while self.buffer_len + frame.len() > self.buffer_size {
+            let oldest = if self.offset > 0 { 1 } else { 0 };
+
+            if let Some(dropped) = self.buffer.remove(oldest) {
+                self.buffer_len -= dropped.len();
+                self.dropped += 1;
+            }
+        }
+
+        self.buffer_len += frame.len();
+        self.buffer.push_back(frame);
+
+        Ok(())
+    }
+
+    /// Write as many buffered bytes as the stream accepts
+    ///
+    /// Several frames are coalesced into a single vectored write. If the
+    /// connection is down or breaks, the frames stay buffered for the next
+    /// connection.
+    fn write_buffered(&mut self) {
+        if self.is_drained() {
+            return;
+        }
+
+        if !self.is_alive() {
+            self.disconnect();
+            return;
+        }
+
+        while !self.buffer.is_empty() {
+            let offset = self.offset;
+            let slices: Vec<IoSlice> = self.buffer
+                .iter()
+                .take(MAX_FRAMES_PER_WRITE)
+                .enumerate()
+                .map(|(i, frame)| if i == 0 {
+                    IoSlice::new(&frame[offset..])
+                } else {
+                    IoSlice::new(frame)
+                })
+                .collect();
+
+            let stream = self.stream.as_mut().expect("Connection is established");
+            let result = stream.write_vectored(&slices);
+            drop(slices);
+
+            match result {
+                Ok(0) => {
+                    debug!("GELF connection refuses to accept data");
+                    self.disconnect();
+                    return;
+                }
+                Ok(written) => self.consume(written),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    debug!("GELF connection broke: {}", e);
+                    self.disconnect();
+                    return;
+                }
+            }
+        }
+
+        // Streams with their own buffering (e.g. TLS) need to be flushed
+        let stream = self.stream.as_mut().expect("Connection is established");
+        match stream.flush() {
+            Ok(_) => self.unflushed = false,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => self.unflushed = true,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => self.unflushed = true,
+            Err(e) => {
+                debug!("GELF connection broke: {}", e);
+                self.disconnect();
+            }
+        }
+    }
+
+    /// Remove `written` bytes from the front of the outgoing buffer
+    fn consume(&mut self, mut written: usize) {
+        while written > 0 {
+            let remaining = self.buffer[0].len() - self.offset;
+
+            if written < remaining {
+                self.offset += written;
+                return;
+            }
+
+            written -= remaining;
+            self.offset = 0;
+
+            if let Some(frame) = self.buffer.pop_front() {
+                self.buffer_len -= frame.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Connects once, every later attempt takes long and fails
+    struct SlowConnector {
+        address: net::SocketAddr,
+        attempts: Arc<AtomicUsize>,
+    }
+
+    impl Connector for SlowConnector {
+        fn connect(&self, _timeout: Duration) -> io::Result<Box<dyn Stream>> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) > 0 {
+                thread::sleep(Duration::from_millis(500));
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "Connection timed out"));
+            }
+
+            let stream = net::TcpStream::connect(self.address)?;
+            stream.set_nonblocking(true)?;
+            Ok(Box::new(stream))
+        }
+    }
+
+    #[test]
+    fn reconnect_in_background() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let connector = SlowConnector {
+            address: listener.local_addr().unwrap(),
+            attempts: attempts.clone(),
+        };
+
+        let sender = StreamSender::new(Box::new(connector), "test").unwrap();
+        {
+            let mut connection = sender.connection();
+            connection.backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(1));
+            connection.flush_timeout = Duration::from_millis(10);
+        }
+
+        let (conn, _) = listener.accept().unwrap();
+        drop(conn);
+        thread::sleep(Duration::from_millis(50));
+
+        // Detect the broken connection, the writer starts reconnecting
+        sender.send(b"first".to_vec()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        let start = Instant::now();
+        sender.send(b"second".to_vec()).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(250));
+        assert_eq!(sender.connection().buffered_frames(), 2);
+    }
+}
@@ -1,10 +1,13 @@
-use failure;
 use failure::Fail;
-use std::io::Write;
+use std::io;
 use std::net;
-use std::sync;
+use std::time::Duration;
 
 use crate::{Result, Error, Backend, WireMessage};
+use crate::backends::backoff::Backoff;
+use crate::backends::stream::{Connector, Stream, StreamSender};
+#[cfg(feature = "tls")]
+use crate::backends::tls::{TlsConfig, TlsConnector};
 
 /// TcpBackend is a simple GELF over TCP backend.
 ///
@@ -12,57 +15,368 @@ use crate::{Result, Error, Backend, WireMessage};
 /// a Gelf host over TCP. TCP's stream-based nature requires no chunking.
 /// GELF over TCP does not support any type of compression, due to the use of
 /// the null byte as a frame delimiter.
+///
+/// Frames are appended to a bounded outgoing buffer and written to the
+/// non-blocking socket with as few (vectored) writes as possible. Partially
+/// written frames are tracked, so frames never interleave or get truncated.
+/// Whatever the socket doesn't accept immediately is written by a background
+/// writer thread, or by an explicit `Backend::flush`.
+///
+/// A broken connection is detected on the next write. The background writer
+/// then reconnects with an exponential backoff (with jitter), trying all
+/// addresses the destination resolved to. Frames stay buffered while disconnected and are
+/// sent once the connection is re-established. If the buffer overflows, the
+/// oldest frames are dropped.
+///
+/// With the `tls` feature the connection can be encrypted with TLS, see
+/// `TcpBackend::new_with_tls`.
 pub struct TcpBackend {
-    socket: sync::Arc<sync::Mutex<net::TcpStream>>,
+    sender: StreamSender,
+}
+
+/// TcpConnector connects to the first reachable address of a destination
+pub struct TcpConnector {
+    addresses: Vec<net::SocketAddr>,
 }
 
 impl TcpBackend {
     /// Construct a new TcpBackend.
+    ///
+    /// The destination is resolved once. Construction fails if none of the resolved
+    /// addresses accepts a connection.
     pub fn new<T: net::ToSocketAddrs>(destination: T) -> Result<TcpBackend> {
-        let socket = net::TcpStream::connect(destination).map_err(|e| {
-            failure::Error::from(e)
-                .context("Failed to establish TCP connection")
-                .context(Error::BackendCreationFailed)
-        })?;
+        let connector = TcpConnector::new(destination)?;
 
-        socket.set_nonblocking(true).map_err(|e| {
-            e.context("Failed to set TcpStream to non-blocking mode")
-                .context(Error::BackendCreationFailed)
-        })?;
+        Ok(TcpBackend {
+            sender: StreamSender::new(Box::new(connector), "tcp")?,
+        })
+    }
+
+    /// Construct a new TcpBackend speaking TLS.
+    ///
+    /// The frames are sent with the same null-byte framing as plaintext GELF over TCP.
+    /// Construction fails if none of the resolved addresses accepts a connection or
+    /// the TLS handshake fails.
+    #[cfg(feature = "tls")]
+    pub fn new_with_tls<T: net::ToSocketAddrs>(destination: T, config: &TlsConfig) -> Result<TcpBackend> {
+        let connector = TlsConnector::new(TcpConnector::new(destination)?, config)?;
 
         Ok(TcpBackend {
-            socket: sync::Arc::new(sync::Mutex::new(socket)),
+            sender: StreamSender::new(Box::new(connector), "tls")?,
         })
     }
+
+    /// Return the timeout for connecting to a single address
+    pub fn connect_timeout(&self) -> Duration {
+        self.sender.connection().connect_timeout
+    }
+
+    /// Set the timeout for connecting to a single address
+    pub fn set_connect_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.sender.connection().connect_timeout = timeout;
+        self
+    }
+
+    /// Return the minimum and maximum delay between reconnect attempts
+    pub fn reconnect_delay(&self) -> (Duration, Duration) {
+        let connection = self.sender.connection();
+        (connection.backoff.min(), connection.backoff.max())
+    }
+
+    /// Set the minimum and maximum delay between reconnect attempts
+    pub fn set_reconnect_delay(&mut self, min: Duration, max: Duration) -> &mut Self {
+        self.sender.connection().backoff = Backoff::new(min, max);
+        self
+    }
+
+    /// Return the maximum number of bytes in the outgoing buffer
+    pub fn buffer_size(&self) -> usize {
+        self.sender.connection().buffer_size
+    }
+
+    /// Set the maximum number of bytes in the outgoing buffer
+    ///
+    /// The buffer holds all frames which are not yet written to the socket,
+    /// including those logged while disconnected.
+    pub fn set_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.sender.connection().buffer_size = size;
+        self
+    }
+
+    /// Return the time `flush` waits for the outgoing buffer to drain
+    pub fn flush_timeout(&self) -> Duration {
+        self.sender.connection().flush_timeout
+    }
+
+    /// Set the time `flush` waits for the outgoing buffer to drain
+    pub fn set_flush_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.sender.connection().flush_timeout = timeout;
+        self
+    }
+
+    /// Return whether the backend currently holds an established connection
+    pub fn is_connected(&self) -> bool {
+        self.sender.connection().is_connected()
+    }
+
+    /// Return the number of bytes waiting in the outgoing buffer
+    pub fn buffered_bytes(&self) -> usize {
+        self.sender.connection().buffered_bytes()
+    }
+
+    /// Return the number of frames dropped due to a full outgoing buffer
+    pub fn dropped_messages(&self) -> u64 {
+        self.sender.connection().dropped()
+    }
 }
 
 impl Backend for TcpBackend {
     /// Log a message over TCP.
+    ///
+    /// The message is appended to the outgoing buffer and as much of the buffer
+    /// as the socket accepts is written right away. This only fails if the
+    /// message had to be dropped.
     fn log_message(&self, msg: WireMessage) -> Result<()> {
         let mut msg: Vec<u8> = msg.to_gelf()?.into();
 
         // raw messages need to be terminated with a 0-byte
         msg.push(0x00);
 
-        let mut socket = self.socket.lock().unwrap();
+        self.sender.send(msg)
+    }
+
+    /// Wait until all buffered frames are written to the socket
+    ///
+    /// Fails if the buffer can't be drained within the flush timeout.
+    fn flush(&self) -> Result<()> {
+        self.sender.flush()
+    }
+}
+
+impl TcpConnector {
+    /// Construct a new TcpConnector for all addresses `destination` resolves to
+    pub fn new<T: net::ToSocketAddrs>(destination: T) -> Result<TcpConnector> {
+        let addresses: Vec<net::SocketAddr> = destination
+            .to_socket_addrs()
+            .map_err(|e| {
+                failure::Error::from(e)
+                    .context("Failed to parse a destination address")
+                    .context(Error::BackendCreationFailed)
+            })?
+            .collect();
+
+        if addresses.is_empty() {
+            return Err(format_err!("Invalid destination server address")
+                .context(Error::BackendCreationFailed)
+                .into());
+        }
+
+        Ok(TcpConnector { addresses })
+    }
+
+    /// Try to connect to every address until one succeeds
+    ///
+    /// The returned stream is in blocking mode.
+    pub fn connect_tcp(&self, timeout: Duration) -> io::Result<net::TcpStream> {
+        let mut last_error = io::Error::other("No address to connect to");
+
+        for address in self.addresses.iter() {
+            match net::TcpStream::connect_timeout(address, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+}
 
-        socket
-            .write_all(&msg)
-            .map_err(|e| e.context(Error::LogTransmitFailed))?;
+impl Connector for TcpConnector {
+    fn connect(&self, timeout: Duration) -> io::Result<Box<dyn Stream>> {
+        let stream = self.connect_tcp(timeout)?;
+        stream.set_nonblocking(true)?;
 
-        Ok(())
+        Ok(Box::new(stream))
     }
 }
 
-impl Drop for TcpBackend {
-    /// Try to close the connection gracefully when TcpBackend goes out of scope
-    fn drop(&mut self) {
-        // When drop() is called unwrap() should never fail
-        let mut socket = self.socket.lock().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::thread;
+    use crate::backends::test_util::{log, short_message};
+
+    /// Read `num` null-delimited frames and return their short messages
+    fn read_frames(stream: &mut net::TcpStream, num: usize) -> Vec<String> {
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let mut reader = io::BufReader::new(stream);
+        let mut frames = Vec::new();
+        let mut frame = Vec::new();
+
+        while frames.len() < num {
+            reader.read_until(0, &mut frame).expect("Failed to read frame");
+            assert_eq!(frame.pop(), Some(0), "Frame is not null-terminated");
+
+            frames.push(short_message(&frame));
+            frame.clear();
+        }
+
+        frames
+    }
+
+    #[test]
+    fn reconnect_after_server_restart() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let mut backend = TcpBackend::new(address).unwrap();
+        backend.set_reconnect_delay(Duration::from_millis(1), Duration::from_millis(10));
+
+        let (mut conn, _) = listener.accept().unwrap();
+        log(&backend, "before").unwrap();
+        assert_eq!(read_frames(&mut conn, 1), vec!["before"]);
+
+        // Kill the server
+        drop(conn);
+        drop(listener);
+        thread::sleep(Duration::from_millis(50));
+
+        log(&backend, "during 1").unwrap();
+        log(&backend, "during 2").unwrap();
+        assert!(!backend.is_connected());
+
+        // Restart the server
+        let listener = net::TcpListener::bind(address).unwrap();
+        thread::sleep(Duration::from_millis(20));
+
+        log(&backend, "after").unwrap();
+
+        // The background writer reconnects
+        backend.flush().unwrap();
+        assert!(backend.is_connected());
+
+        let (mut conn, _) = listener.accept().unwrap();
+        assert_eq!(read_frames(&mut conn, 3), vec!["during 1", "during 2", "after"]);
+    }
+
+    #[test]
+    fn drop_oldest_frames_on_full_buffer() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let mut backend = TcpBackend::new(address).unwrap();
+        backend.set_reconnect_delay(Duration::from_secs(60), Duration::from_secs(60));
+        backend.set_flush_timeout(Duration::from_millis(10));
+
+        let (conn, _) = listener.accept().unwrap();
+        drop(conn);
+        drop(listener);
+        thread::sleep(Duration::from_millis(50));
+
+        // Detect the broken connection and fail the immediate reconnect
+        log(&backend, "msg 1").unwrap();
+        assert!(!backend.is_connected());
+
+        let frame_len = backend.buffered_bytes();
+        backend.set_buffer_size(frame_len * 2);
+
+        log(&backend, "msg 2").unwrap();
+        log(&backend, "msg 3").unwrap();
+
+        assert_eq!(backend.dropped_messages(), 1);
+        assert_eq!(backend.sender.connection().buffered_frames(), 2);
+        assert_eq!(backend.buffered_bytes(), frame_len * 2);
+    }
+
+    #[test]
+    fn keep_buffered_frames_on_oversized_frame() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let mut backend = TcpBackend::new(address).unwrap();
+        backend.set_reconnect_delay(Duration::from_secs(60), Duration::from_secs(60));
+        backend.set_flush_timeout(Duration::from_millis(10));
+
+        let (conn, _) = listener.accept().unwrap();
+        drop(conn);
+        drop(listener);
+        thread::sleep(Duration::from_millis(50));
+
+        // Detect the broken connection and fail the immediate reconnect
+        log(&backend, "msg 1").unwrap();
+        assert!(!backend.is_connected());
+
+        let frame_len = backend.buffered_bytes();
+        backend.set_buffer_size(frame_len * 3);
+        log(&backend, "msg 2").unwrap();
+
+        assert!(log(&backend, &"x".repeat(frame_len * 3)).is_err());
+
+        assert_eq!(backend.dropped_messages(), 1);
+        assert_eq!(backend.sender.connection().buffered_frames(), 2);
+        assert_eq!(backend.buffered_bytes(), frame_len * 2);
+    }
+
+    #[test]
+    fn never_interleave_partially_written_frames() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let mut backend = TcpBackend::new(address).unwrap();
+        backend.set_buffer_size(64 * 1024 * 1024);
+
+        let (mut conn, _) = listener.accept().unwrap();
+
+        // Log much more than the socket buffers can hold before the server reads anything
+        let num_messages = 1000;
+        let padding = "x".repeat(16 * 1024);
+        for i in 0..num_messages {
+            log(&backend, &format!("{} {}", i, padding)).unwrap();
+        }
+        assert!(backend.buffered_bytes() > 0);
+
+        let server = thread::spawn(move || read_frames(&mut conn, num_messages));
+
+        backend.flush().unwrap();
+        assert_eq!(backend.buffered_bytes(), 0);
+
+        let expected: Vec<String> = (0..num_messages).map(|i| format!("{} {}", i, padding)).collect();
+        assert_eq!(server.join().unwrap(), expected);
+    }
+
+    #[test]
+    fn background_writer_drains_buffer() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let mut backend = TcpBackend::new(address).unwrap();
+        backend.set_buffer_size(64 * 1024 * 1024);
+
+        let (mut conn, _) = listener.accept().unwrap();
+
+        let padding = "x".repeat(64 * 1024);
+        for i in 0..100 {
+            log(&backend, &format!("{} {}", i, padding)).unwrap();
+        }
+
+        // Nothing but the background writer is sending the remaining bytes
+        let frames = read_frames(&mut conn, 100);
+        assert_eq!(frames[99], format!("99 {}", padding));
+    }
+
+    #[test]
+    fn try_all_resolved_addresses() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // Nothing listens on port 1 on the loopback interface
+        let closed: net::SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let backend = TcpBackend::new(&[closed, address][..]).unwrap();
 
-        socket
-            .flush()
-            .and_then(|_| socket.shutdown(net::Shutdown::Both))
-            .unwrap_or_else(|_| warn!("Failed to flush and shutdown tcp socket cleanly"));
+        let (mut conn, _) = listener.accept().unwrap();
+        log(&backend, "hello").unwrap();
+        assert_eq!(read_frames(&mut conn, 1), vec!["hello"]);
     }
 }
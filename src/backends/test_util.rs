@@ -0,0 +1,111 @@
+//! Helpers shared by the backends' tests
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+
+use crate::{Backend, Logger, Message, NullBackend, Result, WireMessage};
+
+/// Create a message with the given short_message
+pub fn message(short_message: &str) -> WireMessage<'static> {
+    wire_message(Message::new(short_message.to_string()))
+}
+
+/// Create a WireMessage from the given message
+pub fn wire_message(message: Message<'static>) -> WireMessage<'static> {
+    let logger = Logger::new_with_hostname(Box::new(NullBackend::new()), "localhost");
+
+    WireMessage::new(message, &logger).into_owned()
+}
+
+/// Log a message with the given short_message
+pub fn log<B: Backend + ?Sized>(backend: &B, short_message: &str) -> Result<()> {
+    backend.log_message(message(short_message))
+}
+
+/// Return the short_message of a serialized GELF message
+pub fn short_message(json: &[u8]) -> String {
+    let json: serde_json::Value = serde_json::from_slice(json).unwrap();
+
+    json["short_message"].as_str().unwrap().to_string()
+}
+
+/// A backend which fails on demand and records all attempts and sent messages
+#[derive(Clone, Default)]
+pub struct RecordingBackend {
+    failing: Arc<AtomicBool>,
+    attempts: Arc<Mutex<Vec<String>>>,
+    sent: Arc<Mutex<Vec<String>>>,
+}
+
+impl RecordingBackend {
+    /// Let every following message fail or succeed
+    pub fn set_failing(&self, failing: bool) {
+        self.failing.store(failing, Ordering::SeqCst);
+    }
+
+    /// Return the short_messages of all messages, including the failed ones
+    pub fn attempts(&self) -> Vec<String> {
+        self.attempts.lock().unwrap().clone()
+    }
+
+    /// Return the short_messages of all successfully sent messages
+    pub fn sent(&self) -> Vec<String> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+impl Backend for RecordingBackend {
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        let short_message = msg.message().short_message().to_string();
+        self.attempts.lock().unwrap().push(short_message.clone());
+
+        if self.failing.load(Ordering::SeqCst) {
+            bail!("Backend is down");
+        }
+
+        self.sent.lock().unwrap().push(short_message);
+        Ok(())
+    }
+}
+
+/// A backend which reports every message's short_message and every flush
+///
+/// Each message waits for a permit before it is reported.
+pub struct GatedBackend {
+    sent: Mutex<mpsc::Sender<String>>,
+    permits: Mutex<mpsc::Receiver<()>>,
+}
+
+impl GatedBackend {
+    /// Return the backend, the receiver of sent messages and the sender of permits
+    pub fn new() -> (GatedBackend, mpsc::Receiver<String>, mpsc::Sender<()>) {
+        let (sent_tx, sent_rx) = mpsc::channel();
+        let (permit_tx, permit_rx) = mpsc::channel();
+
+        let backend = GatedBackend {
+            sent: Mutex::new(sent_tx),
+            permits: Mutex::new(permit_rx),
+        };
+
+        (backend, sent_rx, permit_tx)
+    }
+}
+
+impl Backend for GatedBackend {
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        self.permits.lock().unwrap().recv().ok();
+
+        // The test may be over and its receiver gone
+        self.sent.lock().unwrap()
+            .send(msg.message().short_message().to_string())
+            .ok();
+
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.sent.lock().unwrap().send("flush".to_string()).ok();
+
+        Ok(())
+    }
+}
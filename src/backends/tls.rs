@@ -0,0 +1,506 @@
+use failure::Fail;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Write};
+use std::net;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme, StreamOwned};
+
+use crate::{Result, Error};
+use crate::backends::stream::{Connector, Stream};
+use crate::backends::tcp::TcpConnector;
+
+/// TlsConfig holds the TLS settings of a GELF over TCP connection
+///
+/// By default the server certificate is verified against the bundled Mozilla
+/// root certificates and the hostname (or SNI name, if overridden).
+///
+/// ```
+/// # use gelf::TlsConfig;
+/// let mut config = TlsConfig::new();
+/// config
+///     .set_server_name("graylog.example.org")
+///     .disable_hostname_verification();
+/// ```
+#[derive(Clone)]
+pub struct TlsConfig {
+    ca_certificates: Vec<CertificateDer<'static>>,
+    client_certificate: Option<(Vec<CertificateDer<'static>>, Arc<PrivateKeyDer<'static>>)>,
+    server_name: Option<String>,
+    verify_hostname: bool,
+}
+
+/// TlsConnector establishes TLS sessions on top of TCP connections
+pub struct TlsConnector {
+    tcp: TcpConnector,
+    config: Arc<ClientConfig>,
+    server_name: Option<ServerName<'static>>,
+}
+
+/// A TLS session over a TCP connection
+pub type TlsStream = StreamOwned<ClientConnection, net::TcpStream>;
+
+/// A server certificate verifier which accepts certificates issued for other hostnames
+#[derive(Debug)]
+struct NoHostnameVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+}
+
+impl TlsConfig {
+    /// Construct a new TlsConfig with default settings
+    pub fn new() -> TlsConfig {
+        TlsConfig {
+            ca_certificates: Vec::new(),
+            client_certificate: None,
+            server_name: None,
+            verify_hostname: true,
+        }
+    }
+
+    /// Trust the CA certificates of a PEM encoded bundle
+    ///
+    /// Once a custom CA is added, the bundled root certificates are no longer trusted.
+    pub fn add_ca_certificates_pem(&mut self, pem: &[u8]) -> Result<&mut Self> {
+        let certificates = parse_certificates(pem)?;
+
+        if certificates.is_empty() {
+            return Err(format_err!("No certificate found in the CA bundle")
+                .context(Error::InvalidTlsConfig)
+                .into());
+        }
+
+        self.ca_certificates.extend(certificates);
+        Ok(self)
+    }
+
+    /// Trust the CA certificates of a PEM encoded bundle file
+    pub fn add_ca_certificates_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self> {
+        self.add_ca_certificates_pem(&read_file(path)?)
+    }
+
+    /// Authenticate with a PEM encoded client certificate (chain) and private key
+    pub fn set_client_certificate_pem(&mut self, certificate: &[u8], key: &[u8]) -> Result<&mut Self> {
+        let certificates = parse_certificates(certificate)?;
+        let key = PrivateKeyDer::from_pem_slice(key).map_err(|e| {
+            failure::Error::from(e)
+                .context("Failed to parse the client private key")
+                .context(Error::InvalidTlsConfig)
+        })?;
+
+        self.client_certificate = Some((certificates, Arc::new(key)));
+        Ok(self)
+    }
+
+    /// Authenticate with a client certificate (chain) and private key from PEM files
+    pub fn set_client_certificate_files<P, Q>(&mut self, certificate: P, key: Q) -> Result<&mut Self>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        self.set_client_certificate_pem(&read_file(certificate)?, &read_file(key)?)
+    }
+
+    /// Return the server name used for SNI and certificate verification
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// Set the server name used for SNI and certificate verification
+    ///
+    /// Without a server name the IP address of the connected server is used.
+    pub fn set_server_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Return a flag whether the server certificate's hostname is verified
+    pub fn hostname_verification(&self) -> bool {
+        self.verify_hostname
+    }
+
+    /// Verify that the server certificate is issued for the server name
+    pub fn enable_hostname_verification(&mut self) -> &mut Self {
+        self.verify_hostname = true;
+        self
+    }
+
+    /// Accept server certificates issued for any hostname
+    ///
+    /// The certificate chain is still verified. This should only be used for testing.
+    pub fn disable_hostname_verification(&mut self) -> &mut Self {
+        self.verify_hostname = false;
+        self
+    }
+
+    /// Build the rustls client configuration
+    fn client_config(&self) -> Result<ClientConfig> {
+        let provider = Arc::new(ring::default_provider());
+
+        let mut roots = RootCertStore::empty();
+        if self.ca_certificates.is_empty() {
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        } else {
+            for certificate in self.ca_certificates.iter() {
+                roots.add(certificate.clone()).map_err(|e| {
+                    failure::Error::from(e)
+                        .context("Failed to add CA certificate")
+                        .context(Error::InvalidTlsConfig)
+                })?;
+            }
+        }
+
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_config_error)?;
+
+        let builder = if self.verify_hostname {
+            builder.with_root_certificates(roots)
+        } else {
+            let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| {
+                    failure::Error::from(e)
+                        .context("Failed to build the certificate verifier")
+                        .context(Error::InvalidTlsConfig)
+                })?;
+
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoHostnameVerifier { inner }))
+        };
+
+        match &self.client_certificate {
+            Some((certificates, key)) => builder
+                .with_client_auth_cert(certificates.clone(), key.clone_key())
+                .map_err(tls_config_error),
+            None => Ok(builder.with_no_client_auth()),
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TlsConnector {
+    /// Construct a new TlsConnector on top of the given TCP connector
+    pub fn new(tcp: TcpConnector, config: &TlsConfig) -> Result<TlsConnector> {
+        let server_name = match config.server_name() {
+            Some(name) => Some(ServerName::try_from(name.to_string()).map_err(|e| {
+                failure::Error::from(e)
+                    .context("Invalid TLS server name")
+                    .context(Error::InvalidTlsConfig)
+            })?),
+            None => None,
+        };
+
+        Ok(TlsConnector {
+            tcp,
+            config: Arc::new(config.client_config()?),
+            server_name,
+        })
+    }
+
+    /// Connect via TCP and complete the TLS handshake
+    ///
+    /// The returned stream is in blocking mode, with `timeout` set as read and write timeout.
+    pub fn connect_tls(&self, timeout: Duration) -> io::Result<TlsStream> {
+        let mut socket = self.tcp.connect_tcp(timeout)?;
+
+        let server_name = match &self.server_name {
+            Some(name) => name.clone(),
+            None => ServerName::IpAddress(socket.peer_addr()?.ip().into()),
+        };
+
+        let mut connection = ClientConnection::new(self.config.clone(), server_name)
+            .map_err(io::Error::other)?;
+
+        socket.set_read_timeout(Some(timeout))?;
+        socket.set_write_timeout(Some(timeout))?;
+
+        while connection.is_handshaking() {
+            connection.complete_io(&mut socket)?;
+        }
+
+        Ok(StreamOwned::new(connection, socket))
+    }
+}
+
+impl Connector for TlsConnector {
+    fn connect(&self, timeout: Duration) -> io::Result<Box<dyn Stream>> {
+        let stream = self.connect_tls(timeout)?;
+
+        stream.sock.set_read_timeout(None)?;
+        stream.sock.set_write_timeout(None)?;
+        stream.sock.set_nonblocking(true)?;
+
+        Ok(Box::new(stream))
+    }
+}
+
+impl Stream for TlsStream {
+    /// Send a close_notify alert and shutdown the TCP connection
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        self.flush()?;
+        self.sock.shutdown(net::Shutdown::Both)
+    }
+}
+
+impl ServerCertVerifier for NoHostnameVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        match self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now) {
+            Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName))
+            | Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForNameContext { .. })) => {
+                Ok(ServerCertVerified::assertion())
+            }
+            result => result,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Parse all certificates of a PEM bundle
+fn parse_certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_slice_iter(pem)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| {
+            failure::Error::from(e)
+                .context("Failed to parse PEM certificates")
+                .context(Error::InvalidTlsConfig)
+                .into()
+        })
+}
+
+fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    fs::read(path.as_ref()).map_err(|e| {
+        failure::Error::from(e)
+            .context(format!("Failed to read '{}'", path.as_ref().display()))
+            .context(Error::InvalidTlsConfig)
+            .into()
+    })
+}
+
+fn tls_config_error(e: rustls::Error) -> failure::Error {
+    failure::Error::from(e)
+        .context(Error::InvalidTlsConfig)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::thread;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::{ServerConfig, ServerConnection};
+    use rustls::crypto::CryptoProvider;
+    use crate::{Backend, TcpBackend};
+    use crate::backends::test_util::{log, short_message};
+
+    fn provider() -> Arc<CryptoProvider> {
+        Arc::new(ring::default_provider())
+    }
+
+    struct Pki {
+        ca_pem: String,
+        ca_der: CertificateDer<'static>,
+        server: (CertificateDer<'static>, PrivateKeyDer<'static>),
+        client_pem: (String, String),
+    }
+
+    /// Create a CA with a server certificate for localhost and a client certificate
+    fn pki() -> Pki {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let server_key = KeyPair::generate().unwrap();
+        let server = CertificateParams::new(vec!["localhost".to_string()]).unwrap()
+            .signed_by(&server_key, &ca, &ca_key)
+            .unwrap();
+
+        let client_key = KeyPair::generate().unwrap();
+        let client = CertificateParams::new(vec!["client".to_string()]).unwrap()
+            .signed_by(&client_key, &ca, &ca_key)
+            .unwrap();
+
+        Pki {
+            ca_pem: ca.pem(),
+            ca_der: ca.der().clone(),
+            server: (
+                server.der().clone(),
+                PrivateKeyDer::from_pem_slice(server_key.serialize_pem().as_bytes()).unwrap(),
+            ),
+            client_pem: (client.pem(), client_key.serialize_pem()),
+        }
+    }
+
+    /// Run a TLS server accepting a single connection and return the received frames
+    fn serve(pki: &Pki, require_client_auth: bool, num: usize) -> (net::SocketAddr, thread::JoinHandle<Vec<String>>) {
+        let builder = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap();
+
+        let builder = if require_client_auth {
+            let mut roots = RootCertStore::empty();
+            roots.add(pki.ca_der.clone()).unwrap();
+
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider())
+                .build()
+                .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let config = Arc::new(builder
+            .with_single_cert(vec![pki.server.0.clone()], pki.server.1.clone_key())
+            .unwrap());
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+            let stream = StreamOwned::new(ServerConnection::new(config).unwrap(), socket);
+            let mut reader = BufReader::new(stream);
+            let mut frames = Vec::new();
+
+            while frames.len() < num {
+                let mut frame = Vec::new();
+                if reader.read_until(0, &mut frame).unwrap_or(0) == 0 {
+                    break;
+                }
+
+                frame.pop();
+                frames.push(short_message(&frame));
+            }
+
+            frames
+        });
+
+        (address, server)
+    }
+
+    #[test]
+    fn send_frames_over_tls() {
+        let pki = pki();
+        let (address, server) = serve(&pki, false, 2);
+
+        let mut config = TlsConfig::new();
+        config
+            .add_ca_certificates_pem(pki.ca_pem.as_bytes()).unwrap()
+            .set_server_name("localhost");
+
+        let backend = TcpBackend::new_with_tls(address, &config).unwrap();
+        log(&backend, "first").unwrap();
+        log(&backend, "second").unwrap();
+        backend.flush().unwrap();
+
+        assert_eq!(server.join().unwrap(), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn reject_certificate_for_other_hostname() {
+        let pki = pki();
+        let (address, _server) = serve(&pki, false, 1);
+
+        let mut config = TlsConfig::new();
+        config
+            .add_ca_certificates_pem(pki.ca_pem.as_bytes()).unwrap()
+            .set_server_name("graylog.example.org");
+
+        assert!(TcpBackend::new_with_tls(address, &config).is_err());
+    }
+
+    #[test]
+    fn accept_certificate_for_other_hostname_without_hostname_verification() {
+        let pki = pki();
+        let (address, server) = serve(&pki, false, 1);
+
+        let mut config = TlsConfig::new();
+        config
+            .add_ca_certificates_pem(pki.ca_pem.as_bytes()).unwrap()
+            .disable_hostname_verification();
+
+        let backend = TcpBackend::new_with_tls(address, &config).unwrap();
+        log(&backend, "unverified").unwrap();
+        drop(backend);
+
+        assert_eq!(server.join().unwrap(), vec!["unverified"]);
+    }
+
+    #[test]
+    fn reject_unknown_ca() {
+        let pki = pki();
+        let (address, _server) = serve(&pki, false, 1);
+
+        let mut config = TlsConfig::new();
+        config
+            .add_ca_certificates_pem(self::pki().ca_pem.as_bytes()).unwrap()
+            .disable_hostname_verification();
+
+        assert!(TcpBackend::new_with_tls(address, &config).is_err());
+    }
+
+    #[test]
+    fn authenticate_with_client_certificate() {
+        let pki = pki();
+        let (address, server) = serve(&pki, true, 1);
+
+        let mut config = TlsConfig::new();
+        config
+            .add_ca_certificates_pem(pki.ca_pem.as_bytes()).unwrap()
+            .set_client_certificate_pem(pki.client_pem.0.as_bytes(), pki.client_pem.1.as_bytes()).unwrap()
+            .set_server_name("localhost");
+
+        let backend = TcpBackend::new_with_tls(address, &config).unwrap();
+        log(&backend, "mutual").unwrap();
+        drop(backend);
+
+        assert_eq!(server.join().unwrap(), vec!["mutual"]);
+    }
+}
@@ -0,0 +1,320 @@
+use std::io;
+use std::net;
+use std::time::Duration;
+
+use crate::message::{ChunkedMessage, CHUNK_OVERHEAD};
+
+/// Maximum number of datagrams passed to a single `sendmmsg` call (UIO_MAXIOV)
+#[cfg(target_os = "linux")]
+const MAX_BATCH_SIZE: usize = 1024;
+
+/// A datagram consisting of a chunk header and a slice of the message payload
+pub struct Datagram<'a> {
+    pub header: &'a [u8],
+    pub payload: &'a [u8],
+    /// Index of the message in the batch
+    pub message: usize,
+}
+
+impl Datagram<'_> {
+    /// Return the size of the datagram
+    pub fn len(&self) -> usize {
+        self.header.len() + self.payload.len()
+    }
+}
+
+/// Batch holds the chunks of one or more messages ready for sending
+///
+/// The headers of all chunks are written to a single buffer, the payloads are
+/// borrowed from the messages. No chunk is copied.
+pub struct Batch<'a> {
+    messages: &'a [ChunkedMessage],
+    headers: Vec<u8>,
+}
+
+impl<'a> Batch<'a> {
+    /// Construct a new Batch from the chunked messages
+    pub fn new(messages: &'a [ChunkedMessage]) -> Batch<'a> {
+        let mut headers = Vec::new();
+        for message in messages {
+            message.write_headers(&mut headers);
+        }
+
+        Batch { messages, headers }
+    }
+
+    /// Return the datagrams of all chunks in order
+    pub fn datagrams(&self) -> Vec<Datagram<'_>> {
+        let mut headers = self.headers.chunks(CHUNK_OVERHEAD as usize);
+        let mut datagrams = Vec::new();
+
+        for (index, message) in self.messages.iter().enumerate() {
+            for chunk_num in 0..message.num_chunks() {
+                let header = if message.num_chunks() > 1 {
+                    headers.next().unwrap_or_default()
+                } else {
+                    &[]
+                };
+
+                datagrams.push(Datagram {
+                    header,
+                    payload: message.chunk_payload(chunk_num),
+                    message: index,
+                });
+            }
+        }
+
+        datagrams
+    }
+}
+
+/// Send the datagrams to the address
+///
+/// Without an address the datagrams are sent to the address the socket is
+/// connected to. Returns the number of datagrams sent and the error which
+/// stopped sending the remaining ones. On Linux all datagrams are sent with as few `sendmmsg`
+/// calls as possible, falling back to `send_to` if the kernel lacks it.
+pub fn send_datagrams(
+    socket: &net::UdpSocket,
+    address: Option<net::SocketAddr>,
+    datagrams: &[Datagram],
+) -> (usize, Option<io::Error>) {
+    #[cfg(target_os = "linux")]
+    {
+        let (sent, error) = linux::send_batched(socket, address, datagrams);
+        match error {
+            Some(ref e) if sent == 0 && e.raw_os_error() == Some(libc::ENOSYS) => (),
+            _ => return (sent, error),
+        }
+    }
+
+    send_each(socket, address, datagrams)
+}
+
+/// Send the datagrams one by one with `send_to`
+fn send_each(
+    socket: &net::UdpSocket,
+    address: Option<net::SocketAddr>,
+    datagrams: &[Datagram],
+) -> (usize, Option<io::Error>) {
+    let mut buf = Vec::new();
+
+    for (sent, datagram) in datagrams.iter().enumerate() {
+        buf.clear();
+        buf.extend_from_slice(datagram.header);
+        buf.extend_from_slice(datagram.payload);
+
+        let result = match address {
+            Some(address) => socket.send_to(&buf, address),
+            None => socket.send(&buf),
+        };
+
+        if let Err(e) = result {
+            return (sent, Some(e));
+        }
+    }
+
+    (datagrams.len(), None)
+}
+
+/// Wait until the socket has room to send
+///
+/// Returns false if the timeout elapsed before.
+#[cfg(unix)]
+pub fn wait_writable(socket: &net::UdpSocket, timeout: Duration) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut fd = libc::pollfd {
+        fd: socket.as_raw_fd(),
+        events: libc::POLLOUT,
+        revents: 0,
+    };
+
+    // Round up, so short timeouts don't turn into a busy loop
+    let millis = timeout.as_micros().div_ceil(1000).min(libc::c_int::MAX as u128) as libc::c_int;
+
+    loop {
+        let result = unsafe { libc::poll(&mut fd, 1, millis) };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+
+            return Err(error);
+        }
+
+        return Ok(result > 0);
+    }
+}
+
+/// Wait until the socket has room to send
+///
+/// Without `poll` this sleeps for a millisecond and lets the caller retry.
+#[cfg(not(unix))]
+pub fn wait_writable(_socket: &net::UdpSocket, timeout: Duration) -> io::Result<bool> {
+    std::thread::sleep(timeout.min(Duration::from_millis(1)));
+    Ok(true)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::os::unix::io::AsRawFd;
+    use std::{cmp, io, mem, net};
+
+    use super::{Datagram, MAX_BATCH_SIZE};
+
+    /// Send the datagrams with `sendmmsg`
+    pub fn send_batched(
+        socket: &net::UdpSocket,
+        address: Option<net::SocketAddr>,
+        datagrams: &[Datagram],
+    ) -> (usize, Option<io::Error>) {
+        let mut addr = address.map(socket_addr);
+
+        let mut iovecs = Vec::with_capacity(datagrams.len() * 2);
+        for datagram in datagrams {
+            iovecs.push(iovec(datagram.header));
+            iovecs.push(iovec(datagram.payload));
+        }
+
+        let mut headers: Vec<libc::mmsghdr> = iovecs.chunks_mut(2)
+            .map(|iov| {
+                // Zeroed to cover the padding fields of some libcs
+                let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+                if let Some((addr, addr_len)) = addr.as_mut() {
+                    hdr.msg_name = addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+                    hdr.msg_namelen = *addr_len;
+                }
+                hdr.msg_iov = iov.as_mut_ptr();
+                hdr.msg_iovlen = iov.len() as _;
+
+                libc::mmsghdr { msg_hdr: hdr, msg_len: 0 }
+            })
+            .collect();
+
+        let mut sent = 0;
+        while sent < headers.len() {
+            let batch = cmp::min(headers.len() - sent, MAX_BATCH_SIZE);
+            let result = unsafe {
+                libc::sendmmsg(socket.as_raw_fd(), headers[sent..].as_mut_ptr(), batch as libc::c_uint, 0)
+            };
+
+            if result < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+
+                return (sent, Some(error));
+            }
+
+            sent += result as usize;
+        }
+
+        (sent, None)
+    }
+
+    /// Return an iovec pointing at the buffer
+    fn iovec(buf: &[u8]) -> libc::iovec {
+        libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        }
+    }
+
+    /// Convert the address to a raw socket address
+    fn socket_addr(address: net::SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+        let len = match address {
+            net::SocketAddr::V4(address) => {
+                let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = address.port().to_be();
+                sin.sin_addr = libc::in_addr {
+                    s_addr: u32::from_ne_bytes(address.ip().octets()),
+                };
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            net::SocketAddr::V6(address) => {
+                let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = address.port().to_be();
+                sin6.sin6_flowinfo = address.flowinfo();
+                sin6.sin6_addr = libc::in6_addr {
+                    s6_addr: address.ip().octets(),
+                };
+                sin6.sin6_scope_id = address.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+
+        (storage, len as libc::socklen_t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChunkSize;
+
+    fn receive_all(socket: &net::UdpSocket, count: usize) -> Vec<Vec<u8>> {
+        let mut buf = [0; 1024];
+        (0..count).map(|_| {
+            let len = socket.recv(&mut buf).unwrap();
+            buf[..len].to_vec()
+        }).collect()
+    }
+
+    fn messages() -> Vec<ChunkedMessage> {
+        vec![
+            ChunkedMessage::new(ChunkSize::Custom(100), vec![1; 250]).unwrap(),
+            ChunkedMessage::new(ChunkSize::Custom(100), vec![2; 50]).unwrap(),
+            ChunkedMessage::new(ChunkSize::Custom(100), vec![3; 101]).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn batch_chunks_of_several_messages() {
+        let messages = messages();
+        let batch = Batch::new(&messages);
+        let datagrams = batch.datagrams();
+
+        let expected: Vec<Vec<u8>> = messages.iter().flat_map(|message| message.iter()).collect();
+        let actual: Vec<Vec<u8>> = datagrams.iter().map(|datagram| [datagram.header, datagram.payload].concat()).collect();
+        assert_eq!(actual, expected);
+
+        let messages: Vec<usize> = datagrams.iter().map(|datagram| datagram.message).collect();
+        assert_eq!(messages, vec![0, 0, 0, 1, 2, 2]);
+    }
+
+    #[test]
+    fn send_batched_and_one_by_one() {
+        let receiver = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let sender = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = receiver.local_addr().unwrap();
+
+        let messages = messages();
+        let batch = Batch::new(&messages);
+        let datagrams = batch.datagrams();
+        let expected: Vec<Vec<u8>> = messages.iter().flat_map(|message| message.iter()).collect();
+
+        let (sent, error) = send_datagrams(&sender, Some(address), &datagrams);
+        assert!(error.is_none());
+        assert_eq!(sent, 6);
+        assert_eq!(receive_all(&receiver, 6), expected);
+
+        let (sent, error) = send_each(&sender, Some(address), &datagrams);
+        assert!(error.is_none());
+        assert_eq!(sent, 6);
+        assert_eq!(receive_all(&receiver, 6), expected);
+
+        sender.connect(address).unwrap();
+        let (sent, error) = send_datagrams(&sender, None, &datagrams);
+        assert!(error.is_none());
+        assert_eq!(sent, 6);
+        assert_eq!(receive_all(&receiver, 6), expected);
+    }
+}
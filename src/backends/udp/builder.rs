@@ -0,0 +1,175 @@
+use std::net;
+use std::sync::atomic::AtomicBool;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use super::socket::{self, SocketOptions};
+use super::{effective_chunk_size, resolve_destination, IpPreference, Target, UdpBackend, DEFAULT_RESOLVE_INTERVAL, DEFAULT_SEND_TIMEOUT};
+use crate::message::ChunkIdGenerator;
+use crate::{ChunkSize, Error, MessageCompression, Result};
+
+/// UdpBackendBuilder constructs a `UdpBackend` with a tuned local socket
+///
+/// The destination may also be an IPv4 or IPv6 multicast group, so several
+/// collectors receive the same stream. The multicast options control how far
+/// the packets travel and which interface they leave through.
+///
+/// ```no_run
+/// # use gelf::{ChunkSize, UdpBackend};
+/// let backend = UdpBackend::builder("graylog.example.com:12201")
+///     .chunk_size(ChunkSize::WAN)
+///     .bind_address("10.0.0.5:0".parse().unwrap())
+///     .send_buffer_size(4 * 1024 * 1024)
+///     .tos(0xb8)
+///     .build()
+///     .unwrap();
+/// ```
+pub struct UdpBackendBuilder<T> {
+    destination: T,
+    chunk_size: ChunkSize,
+    ip_preference: IpPreference,
+    socket_options: SocketOptions,
+    check_timeout: Option<Duration>,
+}
+
+impl<T: net::ToSocketAddrs + Send + Sync + Clone> UdpBackendBuilder<T> {
+    /// Construct a new UdpBackendBuilder for the destination
+    pub fn new(destination: T) -> UdpBackendBuilder<T> {
+        UdpBackendBuilder {
+            destination,
+            chunk_size: ChunkSize::LAN,
+            ip_preference: IpPreference::default(),
+            socket_options: SocketOptions::default(),
+            check_timeout: None,
+        }
+    }
+
+    /// Set the chunk-size (default: ChunkSize::LAN)
+    pub fn chunk_size(&mut self, chunk_size: ChunkSize) -> &mut Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Set the preferred IP version of the destination
+    pub fn ip_preference(&mut self, preference: IpPreference) -> &mut Self {
+        self.ip_preference = preference;
+        self
+    }
+
+    /// Bind the local socket to the address instead of the unspecified one
+    ///
+    /// The address must have the same family as the destination.
+    pub fn bind_address(&mut self, address: net::SocketAddr) -> &mut Self {
+        self.socket_options.bind_address = Some(address);
+        self
+    }
+
+    /// Send all packets through the network interface (`SO_BINDTODEVICE`)
+    ///
+    /// This usually requires the `CAP_NET_RAW` capability.
+    #[cfg(target_os = "linux")]
+    pub fn bind_device<S: Into<String>>(&mut self, interface: S) -> &mut Self {
+        self.socket_options.bind_device = Some(interface.into());
+        self
+    }
+
+    /// Set the size of the socket's send buffer (`SO_SNDBUF`)
+    pub fn send_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.socket_options.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the TTL of IPv4 packets or the hop limit of IPv6 packets
+    pub fn ttl(&mut self, ttl: u32) -> &mut Self {
+        self.socket_options.ttl = Some(ttl);
+        self
+    }
+
+    /// Set the TOS of IPv4 packets or the traffic class of IPv6 packets
+    ///
+    /// The DSCP is the upper six bits, e.g. `0xb8` for Expedited Forwarding.
+    pub fn tos(&mut self, tos: u32) -> &mut Self {
+        self.socket_options.tos = Some(tos);
+        self
+    }
+
+    /// Set the TTL of IPv4 or hop limit of IPv6 multicast packets (default: 1)
+    pub fn multicast_ttl(&mut self, ttl: u32) -> &mut Self {
+        self.socket_options.multicast_ttl = Some(ttl);
+        self
+    }
+
+    /// Set whether multicast packets are looped back to the local host (default: true)
+    pub fn multicast_loop(&mut self, enabled: bool) -> &mut Self {
+        self.socket_options.multicast_loop = Some(enabled);
+        self
+    }
+
+    /// Send IPv4 multicast packets through the interface with the address
+    pub fn multicast_interface_v4(&mut self, interface: net::Ipv4Addr) -> &mut Self {
+        self.socket_options.multicast_interface_v4 = Some(interface);
+        self
+    }
+
+    /// Send IPv6 multicast packets through the interface with the index
+    pub fn multicast_interface_v6(&mut self, index: u32) -> &mut Self {
+        self.socket_options.multicast_interface_v6 = Some(index);
+        self
+    }
+
+    /// Connect the socket to the destination
+    ///
+    /// Only connected sockets receive ICMP port unreachable responses, so
+    /// sending to a port nobody listens on fails with
+    /// `Error::DestinationRefused` instead of losing the messages silently.
+    pub fn connected(&mut self) -> &mut Self {
+        self.socket_options.connect = true;
+        self
+    }
+
+    /// Check the destination on construction (implies `connected`)
+    ///
+    /// This sends an incomplete chunked message as probe, see
+    /// `UdpBackend::check_destination` for details.
+    pub fn check_destination(&mut self, timeout: Duration) -> &mut Self {
+        self.socket_options.connect = true;
+        self.check_timeout = Some(timeout);
+        self
+    }
+
+    /// Resolve the destination, create the socket and construct the UdpBackend
+    pub fn build(&self) -> Result<UdpBackend<T>> {
+        let address = resolve_destination(&self.destination, self.ip_preference)
+            .map_err(|e| e.context(Error::BackendCreationFailed))?;
+
+        let socket = socket::bind(address, &self.socket_options)
+            .map_err(|e| e.context(Error::BackendCreationFailed))?;
+
+        let backend = UdpBackend {
+            destination: self.destination.clone(),
+            target: RwLock::new(Target {
+                socket,
+                address,
+                chunk_size: effective_chunk_size(self.chunk_size, address, &self.socket_options),
+                resolved_at: Instant::now(),
+            }),
+            stale: AtomicBool::new(false),
+            resolving: AtomicBool::new(false),
+            resolve_interval: Some(DEFAULT_RESOLVE_INTERVAL),
+            ip_preference: self.ip_preference,
+            socket_options: self.socket_options.clone(),
+            chunk_size: self.chunk_size,
+            chunk_ids: ChunkIdGenerator::default(),
+            compression: MessageCompression::default(),
+            send_timeout: DEFAULT_SEND_TIMEOUT,
+            abort_on_error: false,
+            pacer: None,
+        };
+
+        if let Some(timeout) = self.check_timeout {
+            backend.check_destination(timeout)?;
+        }
+
+        Ok(backend)
+    }
+}
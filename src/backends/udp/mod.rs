@@ -0,0 +1,827 @@
+use failure::Fail;
+use std::{io, net};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{RwLock, RwLockReadGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{ChunkIdStrategy, ChunkSize, MessageCompression, Result, Error, WireMessage, Backend};
+use crate::errors::UdpSendError;
+use crate::message::{ChunkIdGenerator, ChunkedMessage};
+
+use self::batch::Batch;
+pub use self::builder::UdpBackendBuilder;
+use self::pacer::Pacer;
+pub use self::pacer::Pacing;
+use self::socket::SocketOptions;
+
+mod batch;
+mod builder;
+mod pacer;
+mod socket;
+
+/// Default interval after which the destination is resolved again
+const DEFAULT_RESOLVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Default time to wait for room in the socket's send buffer
+const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// IpPreference selects the address used if a destination resolves to several
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IpPreference {
+    /// Use the first resolved address
+    #[default]
+    Any,
+    /// Use the first IPv4 address if there is one
+    PreferIpv4,
+    /// Use the first IPv6 address if there is one
+    PreferIpv6,
+}
+
+impl IpPreference {
+    /// Select an address from the resolved addresses
+    fn select<I: Iterator<Item = net::SocketAddr>>(self, addrs: I) -> Option<net::SocketAddr> {
+        let addrs: Vec<net::SocketAddr> = addrs.collect();
+        let preferred = match self {
+            IpPreference::Any => None,
+            IpPreference::PreferIpv4 => addrs.iter().find(|addr| addr.is_ipv4()),
+            IpPreference::PreferIpv6 => addrs.iter().find(|addr| addr.is_ipv6()),
+        };
+
+        preferred.or_else(|| addrs.first()).cloned()
+    }
+}
+
+/// UdpBackend is the default and standard GELF backend
+///
+/// It pushes messages to a GELF host via UDP. Messages are cut into chunks
+/// of a certain chunk-size. This size is important since the chunk-size +
+/// a stable overhead of 12 bytes needs to fit the transport layer's mtu.
+///
+/// If the message fits into a single chunk, no chunking is applied. On Linux
+/// all chunks are sent with a single `sendmmsg` system call.
+///
+/// The destination is resolved once and the address is cached. It is
+/// resolved again after the resolve interval elapsed or sending failed, so
+/// changed DNS records are picked up.
+///
+/// If the socket's send buffer is full, sending waits up to the send timeout
+/// for room. Chunks which could not be sent are reported as `UdpSendError`.
+///
+/// Large chunked messages can overflow the receiver's buffer if all chunks
+/// arrive back-to-back. An optional `Pacing` limits the packet and byte rate.
+pub struct UdpBackend<T> {
+    destination: T,
+    target: RwLock<Target>,
+    stale: AtomicBool,
+    resolving: AtomicBool,
+    resolve_interval: Option<Duration>,
+    ip_preference: IpPreference,
+    socket_options: SocketOptions,
+    chunk_size: ChunkSize,
+    chunk_ids: ChunkIdGenerator,
+    compression: MessageCompression,
+    send_timeout: Duration,
+    abort_on_error: bool,
+    pacer: Option<Pacer>,
+}
+
+/// The resolved destination and a local socket of the matching address family
+struct Target {
+    socket: net::UdpSocket,
+    address: net::SocketAddr,
+    chunk_size: ChunkSize,
+    resolved_at: Instant,
+}
+
+impl<T: net::ToSocketAddrs + Send + Sync + Clone> UdpBackend<T> {
+    /// Construct a new UdpBackend with default chunk-size (ChunkSize::LAN)
+    pub fn new(destination: T) -> Result<UdpBackend<T>> {
+        Self::new_with_chunksize(destination, ChunkSize::LAN)
+    }
+
+    /// Construct an new UdpBackend with the given chunk-size
+    pub fn new_with_chunksize(
+        destination: T,
+        chunk_size: ChunkSize,
+    ) -> Result<UdpBackend<T>> {
+        UdpBackendBuilder::new(destination)
+            .chunk_size(chunk_size)
+            .build()
+    }
+
+    /// Return a builder for a UdpBackend with a tuned local socket
+    pub fn builder(destination: T) -> UdpBackendBuilder<T> {
+        UdpBackendBuilder::new(destination)
+    }
+
+    /// Return the chunk-size used for the current destination
+    ///
+    /// With `ChunkSize::Auto` this is the detected size.
+    pub fn chunk_size(&self) -> ChunkSize {
+        self.target.read().unwrap().chunk_size
+    }
+
+    /// Return the current set compression algorithm
+    pub fn compression(&self) -> MessageCompression {
+        self.compression
+    }
+
+    /// Set the compression algorithm
+    pub fn set_compression(&mut self, compression: MessageCompression) -> &mut Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Return the strategy generating the IDs of chunked messages
+    pub fn chunk_id_strategy(&self) -> ChunkIdStrategy {
+        self.chunk_ids.strategy()
+    }
+
+    /// Set the strategy generating the IDs of chunked messages
+    pub fn set_chunk_id_strategy(&mut self, strategy: ChunkIdStrategy) -> &mut Self {
+        self.chunk_ids = ChunkIdGenerator::new(strategy);
+        self
+    }
+
+    /// Return the maximum time to wait for room in the send buffer per call
+    pub fn send_timeout(&self) -> Duration {
+        self.send_timeout
+    }
+
+    /// Set the maximum time to wait for room in the send buffer per call
+    ///
+    /// Time spent waiting for the `Pacing` is not included.
+    pub fn set_send_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.send_timeout = timeout;
+        self
+    }
+
+    /// Return whether the remaining chunks of a message are skipped once one failed
+    pub fn abort_on_error(&self) -> bool {
+        self.abort_on_error
+    }
+
+    /// Skip the remaining chunks of a message once one failed
+    ///
+    /// The receiver can't assemble the message anyway, so this saves bandwidth.
+    pub fn enable_abort_on_error(&mut self) -> &mut Self {
+        self.abort_on_error = true;
+        self
+    }
+
+    /// Send the remaining chunks of a message even if one failed
+    pub fn disable_abort_on_error(&mut self) -> &mut Self {
+        self.abort_on_error = false;
+        self
+    }
+
+    /// Return the pacing of sent packets
+    pub fn pacing(&self) -> Option<Pacing> {
+        self.pacer.as_ref().map(Pacer::pacing)
+    }
+
+    /// Set the pacing of sent packets
+    ///
+    /// This resets the throttling counters.
+    pub fn set_pacing(&mut self, pacing: Option<Pacing>) -> Result<&mut Self> {
+        self.pacer = pacing.map(Pacer::new).transpose()?;
+        Ok(self)
+    }
+
+    /// Return the total time spent waiting for the pacing
+    pub fn throttled_time(&self) -> Duration {
+        self.pacer.as_ref().map_or(Duration::ZERO, Pacer::throttled_time)
+    }
+
+    /// Return the number of packets which had to wait for the pacing
+    pub fn throttled_packets(&self) -> u64 {
+        self.pacer.as_ref().map_or(0, Pacer::throttled_packets)
+    }
+
+    /// Return the interval after which the destination is resolved again
+    pub fn resolve_interval(&self) -> Option<Duration> {
+        self.resolve_interval
+    }
+
+    /// Set the interval after which the destination is resolved again
+    ///
+    /// With `None` the destination is only resolved again after send errors.
+    pub fn set_resolve_interval(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.resolve_interval = interval;
+        self
+    }
+
+    /// Return the preferred IP version
+    pub fn ip_preference(&self) -> IpPreference {
+        self.ip_preference
+    }
+
+    /// Set the preferred IP version and resolve the destination again
+    pub fn set_ip_preference(&mut self, preference: IpPreference) -> Result<&mut Self> {
+        self.ip_preference = preference;
+        self.resolve()?;
+        Ok(self)
+    }
+
+    /// Return the currently used destination address
+    pub fn destination_addr(&self) -> net::SocketAddr {
+        self.target.read().unwrap().address
+    }
+
+    /// Resolve the destination again and return the new address
+    ///
+    /// If resolving fails the previous address is kept.
+    pub fn resolve(&self) -> Result<net::SocketAddr> {
+        self.update_target(None)
+    }
+
+    /// Resolve the destination and update the target
+    ///
+    /// The lookup runs without holding the lock, so logging continues with
+    /// the previous address meanwhile. With `seen` the target is left alone
+    /// if another thread updated it since it was resolved at `seen`.
+    fn update_target(&self, seen: Option<Instant>) -> Result<net::SocketAddr> {
+        let resolved = resolve_destination(&self.destination, self.ip_preference);
+
+        let mut target = self.target.write().unwrap();
+        if seen.is_some_and(|seen| target.resolved_at != seen) {
+            return Ok(target.address);
+        }
+        target.resolved_at = Instant::now();
+        self.stale.store(false, Ordering::SeqCst);
+
+        let address = resolved?;
+        if address.is_ipv4() != target.address.is_ipv4() {
+            target.socket = socket::bind(address, &self.socket_options).map_err(|e| e.context(Error::AddressResolutionFailed))?;
+        } else if self.socket_options.connect && address != target.address {
+            target.socket.connect(address).map_err(|e| {
+                e.context("Failed to connect the socket to the destination")
+                    .context(Error::AddressResolutionFailed)
+            })?;
+        }
+        if address != target.address {
+            target.chunk_size = effective_chunk_size(self.chunk_size, address, &self.socket_options);
+        }
+        target.address = address;
+
+        Ok(address)
+    }
+
+    /// Return whether the socket is connected to the destination
+    pub fn is_connected(&self) -> bool {
+        self.socket_options.connect
+    }
+
+    /// Check that the destination accepts UDP packets
+    ///
+    /// A probe packet is sent and the socket is watched for an ICMP port
+    /// unreachable response until the timeout elapsed. This fails with
+    /// `Error::DestinationRefused` if nothing listens on the destination port.
+    /// Hosts which drop the packet silently can't be detected. The check
+    /// requires a connected socket.
+    ///
+    /// The probe is the first chunk of a two-chunk message with a random ID.
+    /// A GELF server never receives the second chunk and silently discards
+    /// the first one once its reassembly times out.
+    pub fn check_destination(&self, timeout: Duration) -> Result<()> {
+        if !self.socket_options.connect {
+            bail!("Checking the destination requires a connected socket");
+        }
+
+        let target = self.target();
+        let deadline = Instant::now() + timeout;
+        let check_failed = |error: io::Error| match error.kind() {
+            io::ErrorKind::ConnectionRefused => Error::DestinationRefused { address: target.address }.into(),
+            _ => error.context("Failed to check the destination").context(Error::LogTransmitFailed).into(),
+        };
+
+        let probe = ChunkedMessage::new_with_id(ChunkSize::Custom(1), b"{}".to_vec(), rand::random())?
+            .iter()
+            .next()
+            .expect("The probe has two chunks");
+        target.socket.send(&probe).map_err(check_failed)?;
+
+        loop {
+            if let Some(error) = target.socket.take_error().unwrap_or_else(Some) {
+                return Err(check_failed(error));
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+
+            thread::sleep(remaining.min(Duration::from_millis(10)));
+        }
+    }
+
+    /// Log several messages via UDP
+    ///
+    /// The chunks of all messages are sent together, which takes fewer system
+    /// calls than logging the messages one by one.
+    pub fn log_messages(&self, msgs: &[WireMessage]) -> Result<()> {
+        self.send(msgs)
+    }
+
+    /// Compress the message and prepare it for chunking
+    fn chunk(&self, msg: &WireMessage, chunk_size: ChunkSize) -> Result<ChunkedMessage> {
+        let payload = msg.to_compressed_gelf(self.compression)?;
+        ChunkedMessage::new_with_id(chunk_size, payload, self.chunk_ids.next_id(msg.host()))
+    }
+
+    /// Send the chunks of all messages
+    fn send(&self, msgs: &[WireMessage]) -> Result<()> {
+        let target = self.target();
+        let chunked_msgs = msgs.iter()
+            .map(|msg| self.chunk(msg, target.chunk_size))
+            .collect::<Result<Vec<_>>>()?;
+
+        let batch = Batch::new(&chunked_msgs);
+        let datagrams = batch.datagrams();
+        // Time spent waiting for the pacer doesn't count against the timeout
+        let mut deadline = Instant::now() + self.send_timeout;
+
+        let address = if self.socket_options.connect { None } else { Some(target.address) };
+        let mut next = 0;
+        let mut attempted = datagrams.len();
+        let mut failed = 0;
+        let mut first_error = None;
+
+        while next < datagrams.len() {
+            let end = match &self.pacer {
+                Some(pacer) => {
+                    let start = Instant::now();
+                    let granted = pacer.acquire(&datagrams[next..]);
+                    deadline += start.elapsed();
+                    next + granted
+                }
+                None => datagrams.len(),
+            };
+
+            let (sent, error) = batch::send_datagrams(&target.socket, address, &datagrams[next..end]);
+            next += sent;
+
+            if let Some(pacer) = &self.pacer {
+                pacer.refund(&datagrams[next..end]);
+            }
+
+            let error = match error {
+                Some(error) => error,
+                None => continue,
+            };
+
+            if error.kind() == io::ErrorKind::WouldBlock {
+                let now = Instant::now();
+                if now < deadline && batch::wait_writable(&target.socket, deadline - now).unwrap_or(false) {
+                    continue;
+                }
+            } else {
+                self.stale.store(true, Ordering::SeqCst);
+            }
+
+            // Skip the failed chunk, or all remaining chunks of its message
+            let message = datagrams[next].message;
+            let skip_to = if self.abort_on_error {
+                datagrams[next..].iter()
+                    .position(|datagram| datagram.message != message)
+                    .map_or(datagrams.len(), |offset| next + offset)
+            } else {
+                next + 1
+            };
+
+            attempted -= skip_to - next - 1;
+            failed += 1;
+            first_error.get_or_insert(error);
+            next = skip_to;
+        }
+
+        match first_error {
+            Some(ref error) if error.kind() == io::ErrorKind::ConnectionRefused => {
+                Err(Error::DestinationRefused { address: target.address }.into())
+            }
+            Some(error) => Err(UdpSendError::new(attempted - failed, attempted, datagrams.len(), error).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Return the target, resolving the destination again if it is due
+    ///
+    /// Only one thread resolves at a time, the others keep using the
+    /// previous address meanwhile.
+    fn target(&self) -> RwLockReadGuard<'_, Target> {
+        let seen = {
+            let target = self.target.read().unwrap();
+            let expired = self.resolve_interval.is_some_and(|interval| target.resolved_at.elapsed() >= interval);
+            if !expired && !self.stale.load(Ordering::SeqCst) {
+                return target;
+            }
+
+            target.resolved_at
+        };
+
+        if self.resolving.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+            // A failure is not fatal, the previous address is used until the next attempt
+            if self.target.read().unwrap().resolved_at == seen {
+                let _ = self.update_target(Some(seen));
+            }
+            self.resolving.store(false, Ordering::SeqCst);
+        }
+
+        self.target.read().unwrap()
+    }
+}
+
+impl<T: net::ToSocketAddrs + Send + Sync + Clone> Backend for UdpBackend<T> {
+    /// Log a message via UDP.
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        self.send(std::slice::from_ref(&msg))
+    }
+}
+
+/// Return the chunk-size to use for the address, detecting it for `ChunkSize::Auto`
+fn effective_chunk_size(chunk_size: ChunkSize, address: net::SocketAddr, options: &SocketOptions) -> ChunkSize {
+    match chunk_size {
+        ChunkSize::Auto => socket::path_mtu(address, options)
+            .ok()
+            .and_then(|mtu| ChunkSize::from_mtu(mtu, address.is_ipv6()))
+            .unwrap_or(ChunkSize::WAN),
+        chunk_size => chunk_size,
+    }
+}
+
+/// Resolve the destination to a single address
+fn resolve_destination<T: net::ToSocketAddrs>(destination: &T, preference: IpPreference) -> Result<net::SocketAddr> {
+    let addrs = destination
+        .to_socket_addrs()
+        .map_err(|e| {
+            failure::Error::from(e)
+                .context("Failed to resolve the destination address")
+                .context(Error::AddressResolutionFailed)
+        })?;
+
+    preference.select(addrs).ok_or_else(|| {
+        format_err!("Destination did not resolve to any address")
+            .context(Error::AddressResolutionFailed)
+            .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::{Arc, Mutex};
+    use std::vec;
+    use crate::backends::test_util::{log, message};
+
+    /// A destination whose address can be changed like a DNS record
+    #[derive(Clone)]
+    struct MovingDestination(Arc<Mutex<Vec<net::SocketAddr>>>);
+
+    impl net::ToSocketAddrs for MovingDestination {
+        type Iter = vec::IntoIter<net::SocketAddr>;
+
+        fn to_socket_addrs(&self) -> std::io::Result<Self::Iter> {
+            Ok(self.0.lock().unwrap().clone().into_iter())
+        }
+    }
+
+    /// A destination which counts its lookups and takes long to resolve
+    #[derive(Clone)]
+    struct SlowDestination {
+        address: net::SocketAddr,
+        lookups: Arc<AtomicUsize>,
+    }
+
+    impl net::ToSocketAddrs for SlowDestination {
+        type Iter = vec::IntoIter<net::SocketAddr>;
+
+        fn to_socket_addrs(&self) -> std::io::Result<Self::Iter> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(200));
+            Ok(vec![self.address].into_iter())
+        }
+    }
+
+    fn receiver() -> net::UdpSocket {
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        socket
+    }
+
+    fn receive(socket: &net::UdpSocket) -> serde_json::Value {
+        let mut buf = [0; 8192];
+        let len = socket.recv(&mut buf).unwrap();
+        serde_json::from_slice(&buf[..len]).unwrap()
+    }
+
+    #[test]
+    fn select_preferred_ip_version() {
+        let v4: net::SocketAddr = "127.0.0.1:12201".parse().unwrap();
+        let v6: net::SocketAddr = "[::1]:12201".parse().unwrap();
+
+        assert_eq!(IpPreference::Any.select(vec![v6, v4].into_iter()), Some(v6));
+        assert_eq!(IpPreference::PreferIpv4.select(vec![v6, v4].into_iter()), Some(v4));
+        assert_eq!(IpPreference::PreferIpv6.select(vec![v4, v6].into_iter()), Some(v6));
+        assert_eq!(IpPreference::PreferIpv6.select(vec![v4].into_iter()), Some(v4));
+        assert_eq!(IpPreference::Any.select(vec![].into_iter()), None);
+    }
+
+    #[test]
+    fn re_resolve_after_interval() {
+        let (first, second) = (receiver(), receiver());
+        let destination = MovingDestination(Arc::new(Mutex::new(vec![first.local_addr().unwrap()])));
+
+        let mut backend = UdpBackend::new(destination.clone()).unwrap();
+        backend
+            .set_compression(MessageCompression::None)
+            .set_resolve_interval(Some(Duration::from_millis(20)));
+
+        log(&backend, "first").unwrap();
+        assert_eq!(receive(&first)["short_message"], "first");
+
+        *destination.0.lock().unwrap() = vec![second.local_addr().unwrap()];
+        std::thread::sleep(Duration::from_millis(30));
+
+        log(&backend, "second").unwrap();
+        assert_eq!(backend.destination_addr(), second.local_addr().unwrap());
+        assert_eq!(receive(&second)["short_message"], "second");
+    }
+
+    #[test]
+    fn resolve_once_without_blocking_other_threads() {
+        let receiver = receiver();
+        let destination = SlowDestination {
+            address: receiver.local_addr().unwrap(),
+            lookups: Arc::new(AtomicUsize::new(0)),
+        };
+
+        let mut backend = UdpBackend::new(destination.clone()).unwrap();
+        backend
+            .set_compression(MessageCompression::None)
+            .set_resolve_interval(Some(Duration::from_millis(20)));
+        std::thread::sleep(Duration::from_millis(30));
+
+        let durations: Vec<Duration> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|i| {
+                    let backend = &backend;
+                    scope.spawn(move || {
+                        let start = Instant::now();
+                        log(backend, &format!("message {}", i)).unwrap();
+                        start.elapsed()
+                    })
+                })
+                .collect();
+
+            threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+        });
+
+        assert_eq!(destination.lookups.load(Ordering::SeqCst), 2);
+        assert_eq!(durations.iter().filter(|d| **d < Duration::from_millis(100)).count(), 3);
+        for _ in 0..4 {
+            receive(&receiver);
+        }
+    }
+
+    #[test]
+    fn log_several_messages_at_once() {
+        let receiver = receiver();
+
+        let mut backend = UdpBackend::new_with_chunksize(receiver.local_addr().unwrap(), ChunkSize::Custom(40)).unwrap();
+        backend.set_compression(MessageCompression::None);
+
+        let msgs: Vec<WireMessage> = (0..3)
+            .map(|i| message(&format!("message {}", i)))
+            .collect();
+        backend.log_messages(&msgs).unwrap();
+
+        let mut buf = [0; 8192];
+        let mut chunks = Vec::new();
+        while chunks.iter().filter(|chunk: &&Vec<u8>| chunk[10] + 1 == chunk[11]).count() < 3 {
+            let len = receiver.recv(&mut buf).unwrap();
+            chunks.push(buf[..len].to_vec());
+        }
+
+        let expected: Vec<Vec<u8>> = msgs.iter()
+            .flat_map(|msg| msg.to_gelf().unwrap().into_bytes().chunks(40).map(<[u8]>::to_vec).collect::<Vec<_>>())
+            .collect();
+        let payloads: Vec<Vec<u8>> = chunks.iter().map(|chunk| chunk[12..].to_vec()).collect();
+        assert_eq!(payloads, expected);
+    }
+
+    #[test]
+    fn reproduce_chunks_with_fixed_ids() {
+        let receiver = receiver();
+        let msg = message("reproducible");
+
+        let mut chunks = Vec::new();
+        for _ in 0..2 {
+            let mut backend = UdpBackend::new_with_chunksize(receiver.local_addr().unwrap(), ChunkSize::Custom(40)).unwrap();
+            backend.set_chunk_id_strategy(ChunkIdStrategy::CounterFrom(7));
+            backend.log_message(msg.clone()).unwrap();
+
+            let mut buf = [0; 8192];
+            let len = receiver.recv(&mut buf).unwrap();
+            chunks.push(buf[..len].to_vec());
+            for _ in 1..buf[11] {
+                receiver.recv(&mut buf).unwrap();
+            }
+        }
+
+        assert_eq!(chunks[0], chunks[1]);
+        assert_eq!(&chunks[0][2..10], &7_u64.to_be_bytes());
+    }
+
+    #[test]
+    fn report_failed_chunks() {
+        // Sending to the broadcast address is refused without SO_BROADCAST
+        let mut backend = UdpBackend::new_with_chunksize("255.255.255.255:12201", ChunkSize::Custom(40)).unwrap();
+        backend.set_compression(MessageCompression::None);
+
+        let error = log(&backend, "refused").unwrap_err();
+        let error = error.downcast_ref::<UdpSendError>().expect("Should be a UdpSendError");
+        assert!(error.total() > 1);
+        assert_eq!(error.attempted(), error.total());
+        assert_eq!(error.sent(), 0);
+        assert_eq!(error.io_error().kind(), io::ErrorKind::PermissionDenied);
+
+        backend.enable_abort_on_error();
+        let error = log(&backend, "refused").unwrap_err();
+        let error = error.downcast_ref::<UdpSendError>().expect("Should be a UdpSendError");
+        assert_eq!(error.attempted(), 1);
+        assert_eq!(error.sent(), 0);
+    }
+
+    #[test]
+    fn build_with_socket_options() {
+        let receiver = receiver();
+
+        let backend = UdpBackend::builder(receiver.local_addr().unwrap())
+            .bind_address("127.0.0.1:0".parse().unwrap())
+            .ttl(3)
+            .build()
+            .unwrap();
+        log(&backend, "tuned").unwrap();
+
+        let mut buf = [0; 8192];
+        let (_, sender) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(sender.ip(), net::Ipv4Addr::LOCALHOST);
+        assert_eq!(backend.target.read().unwrap().socket.ttl().unwrap(), 3);
+    }
+
+    /// Return an address nobody listens on
+    fn closed_port() -> net::SocketAddr {
+        receiver().local_addr().unwrap()
+    }
+
+    fn is_refused(error: &failure::Error) -> bool {
+        matches!(error.downcast_ref::<Error>(), Some(Error::DestinationRefused { .. }))
+    }
+
+    #[test]
+    fn report_refused_packets_in_connected_mode() {
+        let backend = UdpBackend::builder(closed_port()).connected().build().unwrap();
+        assert!(backend.is_connected());
+
+        // The ICMP response to the first packet fails one of the following sends
+        let refused = (0..10).any(|i| {
+            thread::sleep(Duration::from_millis(10));
+            log(&backend, &i.to_string()).err().is_some_and(|error| is_refused(&error))
+        });
+        assert!(refused);
+    }
+
+    #[test]
+    fn check_destination_on_startup() {
+        let error = UdpBackend::builder(closed_port())
+            .check_destination(Duration::from_millis(500))
+            .build()
+            .err()
+            .expect("The closed port should be refused");
+        assert!(is_refused(&error));
+
+        let receiver = receiver();
+        let backend = UdpBackend::builder(receiver.local_addr().unwrap())
+            .check_destination(Duration::from_millis(50))
+            .build()
+            .unwrap();
+
+        log(&backend, "checked").unwrap();
+
+        // The probe is an incomplete chunked message, which the receiver discards
+        let mut buf = [0; 8192];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 13);
+        assert_eq!(&buf[..2], b"\x1e\x0f");
+        assert_eq!((buf[10], buf[11]), (0, 2));
+        let probe_id = buf[2..10].to_vec();
+
+        let len = receiver.recv(&mut buf).unwrap();
+        assert!(len > 0);
+        assert!(&buf[..2] != b"\x1e\x0f" || buf[2..10] != probe_id[..]);
+
+        let unconnected = UdpBackend::new(receiver.local_addr().unwrap()).unwrap();
+        assert!(unconnected.check_destination(Duration::from_millis(10)).is_err());
+    }
+
+    #[test]
+    fn send_chunks_to_multicast_group() {
+        let group = net::Ipv4Addr::new(239, 255, 42, 99);
+        let receiver = net::UdpSocket::bind((net::Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        receiver.join_multicast_v4(&group, &net::Ipv4Addr::LOCALHOST).unwrap();
+        let port = receiver.local_addr().unwrap().port();
+
+        let mut backend = UdpBackend::builder((group, port))
+            .chunk_size(ChunkSize::Custom(40))
+            .multicast_interface_v4(net::Ipv4Addr::LOCALHOST)
+            .multicast_loop(true)
+            .multicast_ttl(1)
+            .build()
+            .unwrap();
+        backend.set_compression(MessageCompression::None);
+        log(&backend, "multicast").unwrap();
+
+        let mut buf = [0; 8192];
+        let mut payload = Vec::new();
+        loop {
+            let len = receiver.recv(&mut buf).unwrap();
+            payload.extend_from_slice(&buf[12..len]);
+            if buf[10] + 1 == buf[11] {
+                break;
+            }
+        }
+
+        let json: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(json["short_message"], "multicast");
+    }
+
+    #[test]
+    fn pace_chunks_of_large_messages() {
+        let receiver = receiver();
+        let mut backend = UdpBackend::new_with_chunksize(receiver.local_addr().unwrap(), ChunkSize::Custom(40)).unwrap();
+        backend
+            .set_compression(MessageCompression::None)
+            .set_pacing(Some(Pacing {
+                packets_per_second: Some(100),
+                burst_packets: 2,
+                ..Pacing::default()
+            }))
+            .unwrap();
+
+        log(&backend, &"paced ".repeat(40)).unwrap();
+
+        let mut buf = [0; 8192];
+        receiver.recv(&mut buf).unwrap();
+        let num_chunks = buf[11];
+        for _ in 1..num_chunks {
+            receiver.recv(&mut buf).unwrap();
+        }
+
+        assert!(num_chunks > 5);
+        assert!(backend.throttled_packets() > 0);
+        assert!(backend.throttled_time() >= Duration::from_millis(10 * u64::from(num_chunks - 3)));
+    }
+
+    #[test]
+    fn detect_chunk_size_from_mtu() {
+        let receiver = receiver();
+        let mut backend = UdpBackend::new_with_chunksize(receiver.local_addr().unwrap(), ChunkSize::Auto).unwrap();
+        backend.set_compression(MessageCompression::None);
+
+        let detected = backend.chunk_size();
+        assert_ne!(detected, ChunkSize::Auto);
+        if cfg!(target_os = "linux") {
+            assert!(detected.size() >= ChunkSize::WAN.size(), "The loopback MTU should be large");
+        } else {
+            assert_eq!(detected, ChunkSize::WAN);
+        }
+
+        // Every chunk of a message spanning several has the full chunk-size
+        log(&backend, &"x".repeat(usize::from(detected.size()) * 2)).unwrap();
+        let mut buf = [0; 65536];
+        for _ in 0..2 {
+            let len = receiver.recv(&mut buf).unwrap();
+            assert_eq!(len, usize::from(detected.size()) + 12);
+            assert_eq!(&buf[..2], b"\x1e\x0f");
+        }
+    }
+
+    #[test]
+    fn keep_address_if_resolving_fails() {
+        let receiver = receiver();
+        let destination = MovingDestination(Arc::new(Mutex::new(vec![receiver.local_addr().unwrap()])));
+
+        let mut backend = UdpBackend::new(destination.clone()).unwrap();
+        backend
+            .set_compression(MessageCompression::None)
+            .set_resolve_interval(None);
+
+        destination.0.lock().unwrap().clear();
+        assert!(backend.resolve().is_err());
+
+        log(&backend, "still there").unwrap();
+        assert_eq!(receive(&receiver)["short_message"], "still there");
+    }
+}
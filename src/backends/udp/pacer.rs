@@ -0,0 +1,287 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::batch::Datagram;
+use crate::{Error, Result};
+
+/// Pacing limits the rate at which a `UdpBackend` sends packets
+///
+/// The limits apply to all messages sent by the backend. Up to the burst
+/// sizes, packets may be sent back-to-back after an idle period.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pacing {
+    /// Maximum number of packets per second
+    pub packets_per_second: Option<u32>,
+    /// Maximum number of bytes per second
+    pub bytes_per_second: Option<u64>,
+    /// Number of packets which may be sent back-to-back
+    pub burst_packets: u32,
+    /// Number of bytes which may be sent back-to-back
+    pub burst_bytes: u64,
+}
+
+impl Default for Pacing {
+    fn default() -> Pacing {
+        Pacing {
+            packets_per_second: None,
+            bytes_per_second: None,
+            burst_packets: 32,
+            burst_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// Pacer enforces a `Pacing` with a token bucket for packets and one for bytes
+pub struct Pacer {
+    pacing: Pacing,
+    buckets: Mutex<Buckets>,
+    throttled_nanos: AtomicU64,
+    throttled_packets: AtomicU64,
+}
+
+struct Buckets {
+    packets: Option<Bucket>,
+    bytes: Option<Bucket>,
+    refilled_at: Instant,
+}
+
+impl Buckets {
+    /// Add the tokens accumulated since the last refill
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at);
+        self.refilled_at = now;
+
+        for bucket in self.packets.iter_mut().chain(self.bytes.iter_mut()) {
+            bucket.refill(elapsed);
+        }
+    }
+
+    /// Return how long to wait until a packet of `len` bytes may be sent
+    fn wait_time(&self, len: usize) -> Duration {
+        let packets = self.packets.as_ref().map_or(Duration::ZERO, |bucket| bucket.wait_time(1.0));
+        let bytes = self.bytes.as_ref().map_or(Duration::ZERO, |bucket| bucket.wait_time(len as f64));
+
+        packets.max(bytes)
+    }
+
+    /// Take the tokens for a packet of `len` bytes
+    fn take(&mut self, len: usize) {
+        if let Some(bucket) = self.packets.as_mut() {
+            bucket.tokens -= 1.0;
+        }
+        if let Some(bucket) = self.bytes.as_mut() {
+            bucket.tokens -= len as f64;
+        }
+    }
+
+    /// Return the tokens for a packet of `len` bytes which wasn't sent
+    fn give_back(&mut self, len: usize) {
+        if let Some(bucket) = self.packets.as_mut() {
+            bucket.tokens = (bucket.tokens + 1.0).min(bucket.capacity);
+        }
+        if let Some(bucket) = self.bytes.as_mut() {
+            bucket.tokens = (bucket.tokens + len as f64).min(bucket.capacity);
+        }
+    }
+}
+
+/// A token bucket refilled at a constant rate up to its capacity
+struct Bucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+}
+
+impl Bucket {
+    fn new(rate: f64, capacity: f64) -> Bucket {
+        Bucket { rate, capacity, tokens: capacity }
+    }
+
+    /// Add the tokens accumulated during `elapsed`
+    fn refill(&mut self, elapsed: Duration) {
+        self.tokens = (self.tokens + self.rate * elapsed.as_secs_f64()).min(self.capacity);
+    }
+
+    /// Return how long to wait until `cost` tokens are available
+    ///
+    /// A cost above the capacity only requires a full bucket, which then
+    /// goes into debt.
+    fn wait_time(&self, cost: f64) -> Duration {
+        let missing = cost.min(self.capacity) - self.tokens;
+        if missing <= 0.0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_secs_f64(missing / self.rate)
+    }
+}
+
+impl Pacer {
+    /// Construct a new Pacer
+    pub fn new(pacing: Pacing) -> Result<Pacer> {
+        if pacing.packets_per_second == Some(0) || pacing.bytes_per_second == Some(0) {
+            return Err(Error::InvalidPacing { reason: "rates must be greater than 0" }.into());
+        }
+
+        if pacing.burst_packets == 0 || pacing.burst_bytes == 0 {
+            return Err(Error::InvalidPacing { reason: "burst sizes must be greater than 0" }.into());
+        }
+
+        Ok(Pacer {
+            pacing,
+            buckets: Mutex::new(Buckets {
+                packets: pacing.packets_per_second
+                    .map(|rate| Bucket::new(f64::from(rate), f64::from(pacing.burst_packets))),
+                bytes: pacing.bytes_per_second
+                    .map(|rate| Bucket::new(rate as f64, pacing.burst_bytes as f64)),
+                refilled_at: Instant::now(),
+            }),
+            throttled_nanos: AtomicU64::new(0),
+            throttled_packets: AtomicU64::new(0),
+        })
+    }
+
+    /// Return the enforced pacing
+    pub fn pacing(&self) -> Pacing {
+        self.pacing
+    }
+
+    /// Return the total time spent waiting for the rate limits
+    pub fn throttled_time(&self) -> Duration {
+        Duration::from_nanos(self.throttled_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Return the number of packets which had to wait for the rate limits
+    pub fn throttled_packets(&self) -> u64 {
+        self.throttled_packets.load(Ordering::Relaxed)
+    }
+
+    /// Wait until the first datagram may be sent
+    ///
+    /// Returns how many of the datagrams may be sent now, which is at least one.
+    pub fn acquire(&self, datagrams: &[Datagram]) -> usize {
+        let first = match datagrams.first() {
+            Some(datagram) => datagram,
+            None => return 0,
+        };
+        let mut throttled = false;
+
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                buckets.refill();
+
+                let granted = datagrams.iter()
+                    .take_while(|datagram| {
+                        let ready = buckets.wait_time(datagram.len()).is_zero();
+                        if ready {
+                            buckets.take(datagram.len());
+                        }
+                        ready
+                    })
+                    .count();
+
+                if granted > 0 {
+                    return granted;
+                }
+
+                buckets.wait_time(first.len())
+            };
+
+            if !throttled {
+                throttled = true;
+                self.throttled_packets.fetch_add(1, Ordering::Relaxed);
+            }
+
+            let start = Instant::now();
+            thread::sleep(wait);
+            self.throttled_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Return the tokens of acquired datagrams which were not sent
+    pub fn refund(&self, datagrams: &[Datagram]) {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        for datagram in datagrams {
+            buckets.give_back(datagram.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datagrams(payload: &[u8], count: usize) -> Vec<Datagram<'_>> {
+        (0..count).map(|message| Datagram { header: &[], payload, message }).collect()
+    }
+
+    fn send_all(pacer: &Pacer, datagrams: &[Datagram]) {
+        let mut next = 0;
+        while next < datagrams.len() {
+            next += pacer.acquire(&datagrams[next..]);
+        }
+    }
+
+    #[test]
+    fn limit_packets_per_second() {
+        let pacer = Pacer::new(Pacing {
+            packets_per_second: Some(200),
+            burst_packets: 5,
+            ..Pacing::default()
+        }).unwrap();
+
+        // The burst is sent at once, the other 20 packets take 100ms
+        let datagrams = datagrams(b"packet", 25);
+        assert_eq!(pacer.acquire(&datagrams), 5);
+
+        let start = Instant::now();
+        send_all(&pacer, &datagrams[5..]);
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        assert!(pacer.throttled_time() >= Duration::from_millis(90));
+        assert!(pacer.throttled_packets() > 0);
+    }
+
+    #[test]
+    fn limit_bytes_per_second() {
+        let pacer = Pacer::new(Pacing {
+            bytes_per_second: Some(10_000),
+            burst_bytes: 1000,
+            ..Pacing::default()
+        }).unwrap();
+
+        // Packets above the burst size are sent once the bucket is full
+        let payload = [0; 2000];
+        let start = Instant::now();
+        send_all(&pacer, &datagrams(&payload, 2));
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[test]
+    fn refund_unsent_datagrams() {
+        let pacer = Pacer::new(Pacing {
+            packets_per_second: Some(1),
+            burst_packets: 5,
+            ..Pacing::default()
+        }).unwrap();
+
+        let datagrams = datagrams(b"packet", 5);
+        assert_eq!(pacer.acquire(&datagrams), 5);
+
+        // Only two were sent, the other three may be sent right away
+        pacer.refund(&datagrams[2..]);
+        assert_eq!(pacer.acquire(&datagrams[2..]), 3);
+        assert_eq!(pacer.throttled_packets(), 0);
+    }
+
+    #[test]
+    fn reject_zero_rates() {
+        assert!(Pacer::new(Pacing { packets_per_second: Some(0), ..Pacing::default() }).is_err());
+        assert!(Pacer::new(Pacing { burst_bytes: 0, ..Pacing::default() }).is_err());
+        assert!(Pacer::new(Pacing::default()).is_ok());
+    }
+}
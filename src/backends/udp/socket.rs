@@ -0,0 +1,298 @@
+use failure::Fail;
+use std::convert::TryFrom;
+use std::{io, net};
+
+use crate::Result;
+
+/// SocketOptions are applied to the local socket of a `UdpBackend`
+#[derive(Clone, Debug, Default)]
+pub struct SocketOptions {
+    pub bind_address: Option<net::SocketAddr>,
+    pub bind_device: Option<String>,
+    pub send_buffer_size: Option<usize>,
+    pub ttl: Option<u32>,
+    pub tos: Option<u32>,
+    pub connect: bool,
+    pub multicast_ttl: Option<u32>,
+    pub multicast_loop: Option<bool>,
+    pub multicast_interface_v4: Option<net::Ipv4Addr>,
+    pub multicast_interface_v6: Option<u32>,
+}
+
+/// Socket options the standard library can't set
+///
+/// They are set with `setsockopt` on unix, other platforms fail to set them.
+#[derive(Clone, Copy, Debug)]
+enum SocketOption {
+    #[cfg(target_os = "linux")]
+    BindDevice,
+    SendBufferSize,
+    UnicastHopsV6,
+    TosV4,
+    TrafficClassV6,
+    MulticastHopsV6,
+    MulticastInterfaceV4,
+    MulticastInterfaceV6,
+}
+
+#[cfg(unix)]
+impl SocketOption {
+    /// Return the level and name of the option
+    fn raw(self) -> (libc::c_int, libc::c_int) {
+        match self {
+            #[cfg(target_os = "linux")]
+            SocketOption::BindDevice => (libc::SOL_SOCKET, libc::SO_BINDTODEVICE),
+            SocketOption::SendBufferSize => (libc::SOL_SOCKET, libc::SO_SNDBUF),
+            SocketOption::UnicastHopsV6 => (libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS),
+            SocketOption::TosV4 => (libc::IPPROTO_IP, libc::IP_TOS),
+            SocketOption::TrafficClassV6 => (libc::IPPROTO_IPV6, libc::IPV6_TCLASS),
+            SocketOption::MulticastHopsV6 => (libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_HOPS),
+            SocketOption::MulticastInterfaceV4 => (libc::IPPROTO_IP, libc::IP_MULTICAST_IF),
+            SocketOption::MulticastInterfaceV6 => (libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_IF),
+        }
+    }
+}
+
+/// Create an appropiate local socket for the given destination
+pub fn bind(destination: net::SocketAddr, options: &SocketOptions) -> Result<net::UdpSocket> {
+    let local = match (options.bind_address, destination) {
+        (Some(local), _) if local.is_ipv4() == destination.is_ipv4() => local,
+        (Some(local), _) => bail!(
+            "The bind address {} does not match the address family of {}",
+            local,
+            destination
+        ),
+        (None, net::SocketAddr::V4(_)) => (net::Ipv4Addr::UNSPECIFIED, 0).into(),
+        (None, net::SocketAddr::V6(_)) => (net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+
+    let socket = net::UdpSocket::bind(local)
+        .map_err(|e| e.context("Failed to bind local socket"))?;
+
+    socket.set_nonblocking(true)
+        .map_err(|e| e.context("Failed to set UdpSocket to non-blocking mode"))?;
+
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(device) = &options.bind_device {
+            set_option(&socket, SocketOption::BindDevice, device.as_bytes())
+                .map_err(|e| e.context("Failed to bind the socket to the device"))?;
+        }
+    }
+
+    if let Some(size) = options.send_buffer_size {
+        set_int_option(&socket, SocketOption::SendBufferSize, size)
+            .map_err(|e| e.context("Failed to set the send buffer size"))?;
+    }
+
+    if let Some(ttl) = options.ttl {
+        let result = match destination {
+            net::SocketAddr::V4(_) => socket.set_ttl(ttl),
+            net::SocketAddr::V6(_) => set_int_option(&socket, SocketOption::UnicastHopsV6, ttl as usize),
+        };
+        result.map_err(|e| e.context("Failed to set the TTL"))?;
+    }
+
+    if let Some(tos) = options.tos {
+        let result = match destination {
+            net::SocketAddr::V4(_) => set_int_option(&socket, SocketOption::TosV4, tos as usize),
+            net::SocketAddr::V6(_) => set_int_option(&socket, SocketOption::TrafficClassV6, tos as usize),
+        };
+        result.map_err(|e| e.context("Failed to set the TOS"))?;
+    }
+
+    if let Some(ttl) = options.multicast_ttl {
+        let result = match destination {
+            net::SocketAddr::V4(_) => socket.set_multicast_ttl_v4(ttl),
+            net::SocketAddr::V6(_) => set_int_option(&socket, SocketOption::MulticastHopsV6, ttl as usize),
+        };
+        result.map_err(|e| e.context("Failed to set the multicast TTL"))?;
+    }
+
+    if let Some(enabled) = options.multicast_loop {
+        let result = match destination {
+            net::SocketAddr::V4(_) => socket.set_multicast_loop_v4(enabled),
+            net::SocketAddr::V6(_) => socket.set_multicast_loop_v6(enabled),
+        };
+        result.map_err(|e| e.context("Failed to set the multicast loopback"))?;
+    }
+
+    if let (net::SocketAddr::V4(_), Some(interface)) = (destination, options.multicast_interface_v4) {
+        set_option(&socket, SocketOption::MulticastInterfaceV4, &interface.octets())
+            .map_err(|e| e.context("Failed to set the outgoing multicast interface"))?;
+    }
+
+    if let (net::SocketAddr::V6(_), Some(index)) = (destination, options.multicast_interface_v6) {
+        set_int_option(&socket, SocketOption::MulticastInterfaceV6, index as usize)
+            .map_err(|e| e.context("Failed to set the outgoing multicast interface"))?;
+    }
+
+    if options.connect {
+        socket.connect(destination)
+            .map_err(|e| e.context("Failed to connect the socket to the destination"))?;
+    }
+
+    Ok(socket)
+}
+
+/// Return the MTU of the route to the destination
+///
+/// A probe socket with the same options is connected to the destination,
+/// so the kernel looks up the route including the outgoing interface.
+#[cfg(target_os = "linux")]
+pub fn path_mtu(destination: net::SocketAddr, options: &SocketOptions) -> Result<u32> {
+    let options = SocketOptions {
+        connect: true,
+        ..options.clone()
+    };
+    let socket = bind(destination, &options)?;
+
+    let mtu = match destination {
+        net::SocketAddr::V4(_) => get_int_option(&socket, libc::IPPROTO_IP, libc::IP_MTU),
+        net::SocketAddr::V6(_) => get_int_option(&socket, libc::IPPROTO_IPV6, libc::IPV6_MTU),
+    };
+    let mtu = mtu.map_err(|e| e.context("Failed to get the MTU of the route"))?;
+
+    Ok(u32::try_from(mtu)?)
+}
+
+/// Return the MTU of the route to the destination
+#[cfg(not(target_os = "linux"))]
+pub fn path_mtu(_destination: net::SocketAddr, _options: &SocketOptions) -> Result<u32> {
+    bail!("Looking up the MTU is not supported on this platform")
+}
+
+/// Get an integer socket option
+#[cfg(target_os = "linux")]
+fn get_int_option(socket: &net::UdpSocket, level: libc::c_int, name: libc::c_int) -> io::Result<libc::c_int> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(socket.as_raw_fd(), level, name, &mut value as *mut _ as *mut libc::c_void, &mut len)
+    };
+
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(value)
+}
+
+/// Set an integer socket option
+fn set_int_option(socket: &net::UdpSocket, option: SocketOption, value: usize) -> io::Result<()> {
+    let value = i32::try_from(value)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Socket option value is too large"))?;
+
+    set_option(socket, option, &value.to_ne_bytes())
+}
+
+/// Set a socket option to the raw value
+#[cfg(unix)]
+fn set_option(socket: &net::UdpSocket, option: SocketOption, value: &[u8]) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let (level, name) = option.raw();
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            value.as_ptr() as *const libc::c_void,
+            value.len() as libc::socklen_t,
+        )
+    };
+
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Set a socket option to the raw value
+#[cfg(not(unix))]
+fn set_option(_socket: &net::UdpSocket, option: SocketOption, _value: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("The socket option {:?} is not supported on this platform", option),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_with_options() {
+        let destination = "127.0.0.1:12201".parse().unwrap();
+        let options = SocketOptions {
+            bind_address: Some("127.0.0.1:0".parse().unwrap()),
+            send_buffer_size: Some(64 * 1024),
+            ttl: Some(7),
+            tos: Some(0xb8),
+            ..SocketOptions::default()
+        };
+
+        let socket = bind(destination, &options).unwrap();
+        assert_eq!(socket.local_addr().unwrap().ip(), net::Ipv4Addr::LOCALHOST);
+        assert_eq!(socket.ttl().unwrap(), 7);
+
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(get_int_option(&socket, libc::IPPROTO_IP, libc::IP_TOS).unwrap(), 0xb8);
+            assert!(get_int_option(&socket, libc::SOL_SOCKET, libc::SO_SNDBUF).unwrap() >= 64 * 1024);
+        }
+    }
+
+    #[test]
+    fn bind_with_multicast_options() {
+        let options = SocketOptions {
+            multicast_ttl: Some(4),
+            multicast_loop: Some(false),
+            multicast_interface_v4: Some(net::Ipv4Addr::LOCALHOST),
+            ..SocketOptions::default()
+        };
+
+        let socket = bind("239.1.2.3:12201".parse().unwrap(), &options).unwrap();
+        assert_eq!(socket.multicast_ttl_v4().unwrap(), 4);
+        assert!(!socket.multicast_loop_v4().unwrap());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+
+            let mut interface = [0_u8; 4];
+            let mut len = interface.len() as libc::socklen_t;
+            let result = unsafe {
+                libc::getsockopt(
+                    socket.as_raw_fd(),
+                    libc::IPPROTO_IP,
+                    libc::IP_MULTICAST_IF,
+                    interface.as_mut_ptr() as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            assert_eq!(result, 0);
+            assert_eq!(net::Ipv4Addr::from(interface), net::Ipv4Addr::LOCALHOST);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn look_up_path_mtu() {
+        let mtu = path_mtu("127.0.0.1:12201".parse().unwrap(), &SocketOptions::default()).unwrap();
+        assert!(mtu >= 1280);
+    }
+
+    #[test]
+    fn reject_bind_address_of_other_family() {
+        let options = SocketOptions {
+            bind_address: Some("[::1]:0".parse().unwrap()),
+            ..SocketOptions::default()
+        };
+
+        assert!(bind("127.0.0.1:12201".parse().unwrap(), &options).is_err());
+    }
+}
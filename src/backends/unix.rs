@@ -0,0 +1,388 @@
+use failure::Fail;
+use std::io;
+use std::net;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixStream};
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{Backend, ChunkSize, Error, MessageCompression, Result, WireMessage};
+use crate::backends::backoff::Backoff;
+use crate::backends::stream::{Connector, Stream, StreamSender};
+
+/// UnixDatagramBackend sends GELF messages to a local Unix datagram socket
+///
+/// Messages are compressed and chunked exactly like with `UdpBackend`, so any
+/// GELF UDP receiver listening on a Unix socket understands them. Other than
+/// with UDP, the receiver can identify the sending process.
+///
+/// On Linux, sockets in the abstract namespace are supported with
+/// `UnixDatagramBackend::new_abstract`.
+pub struct UnixDatagramBackend {
+    socket: UnixDatagram,
+    destination: SocketAddr,
+    chunk_size: ChunkSize,
+    compression: MessageCompression,
+}
+
+/// UnixStreamBackend sends GELF messages to a local Unix stream socket
+///
+/// Messages are framed with a null byte, exactly like with `TcpBackend`, and
+/// share its buffering and reconnect behaviour.
+///
+/// On Linux, sockets in the abstract namespace are supported with
+/// `UnixStreamBackend::new_abstract`.
+pub struct UnixStreamBackend {
+    sender: StreamSender,
+}
+
+/// UnixConnector connects to a Unix stream socket
+struct UnixConnector {
+    destination: SocketAddr,
+}
+
+impl UnixDatagramBackend {
+    /// Construct a new UnixDatagramBackend with default chunk-size (ChunkSize::LAN)
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<UnixDatagramBackend> {
+        Self::new_with_chunksize(path, ChunkSize::LAN)
+    }
+
+    /// Construct a new UnixDatagramBackend with the given chunk-size
+    pub fn new_with_chunksize<P: AsRef<Path>>(path: P, chunk_size: ChunkSize) -> Result<UnixDatagramBackend> {
+        Self::new_with_address(pathname_address(path.as_ref())?, chunk_size)
+    }
+
+    /// Construct a new UnixDatagramBackend for a socket in the abstract namespace
+    ///
+    /// The name is given without the leading null byte.
+    #[cfg(target_os = "linux")]
+    pub fn new_abstract(name: &[u8], chunk_size: ChunkSize) -> Result<UnixDatagramBackend> {
+        Self::new_with_address(abstract_address(name)?, chunk_size)
+    }
+
+    fn new_with_address(destination: SocketAddr, chunk_size: ChunkSize) -> Result<UnixDatagramBackend> {
+        let socket = UnixDatagram::unbound().map_err(|e| {
+            e.context("Failed to create local socket")
+                .context(Error::BackendCreationFailed)
+        })?;
+
+        socket.set_nonblocking(true).map_err(|e| {
+            e.context("Failed to set UnixDatagram to non-blocking mode")
+                .context(Error::BackendCreationFailed)
+        })?;
+
+        Ok(UnixDatagramBackend {
+            socket,
+            destination,
+            chunk_size,
+            compression: MessageCompression::default(),
+        })
+    }
+
+    /// Return the current set compression algorithm
+    pub fn compression(&self) -> MessageCompression {
+        self.compression
+    }
+
+    /// Set the compression algorithm
+    pub fn set_compression(&mut self, compression: MessageCompression) -> &mut Self {
+        self.compression = compression;
+        self
+    }
+}
+
+impl Backend for UnixDatagramBackend {
+    /// Log a message via the Unix datagram socket.
+    ///
+    /// Fails if the socket doesn't exist or its receive queue is full.
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        let chunked_msg = msg.to_chunked_message(self.chunk_size, self.compression)?;
+
+        for chunk in chunked_msg.iter() {
+            self.socket.send_to_addr(&chunk, &self.destination).map_err(|e| {
+                e.context("Failed to send chunk to the Unix datagram socket")
+                    .context(Error::LogTransmitFailed)
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl UnixStreamBackend {
+    /// Construct a new UnixStreamBackend.
+    ///
+    /// Construction fails if the socket doesn't accept a connection.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<UnixStreamBackend> {
+        Self::new_with_address(pathname_address(path.as_ref())?)
+    }
+
+    /// Construct a new UnixStreamBackend for a socket in the abstract namespace
+    ///
+    /// The name is given without the leading null byte.
+    #[cfg(target_os = "linux")]
+    pub fn new_abstract(name: &[u8]) -> Result<UnixStreamBackend> {
+        Self::new_with_address(abstract_address(name)?)
+    }
+
+    fn new_with_address(destination: SocketAddr) -> Result<UnixStreamBackend> {
+        let connector = UnixConnector { destination };
+
+        Ok(UnixStreamBackend {
+            sender: StreamSender::new(Box::new(connector), "unix")?,
+        })
+    }
+
+    /// Return the minimum and maximum delay between reconnect attempts
+    pub fn reconnect_delay(&self) -> (Duration, Duration) {
+        let connection = self.sender.connection();
+        (connection.backoff.min(), connection.backoff.max())
+    }
+
+    /// Set the minimum and maximum delay between reconnect attempts
+    pub fn set_reconnect_delay(&mut self, min: Duration, max: Duration) -> &mut Self {
+        self.sender.connection().backoff = Backoff::new(min, max);
+        self
+    }
+
+    /// Return the maximum number of bytes in the outgoing buffer
+    pub fn buffer_size(&self) -> usize {
+        self.sender.connection().buffer_size
+    }
+
+    /// Set the maximum number of bytes in the outgoing buffer
+    pub fn set_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.sender.connection().buffer_size = size;
+        self
+    }
+
+    /// Return the time `flush` waits for the outgoing buffer to drain
+    pub fn flush_timeout(&self) -> Duration {
+        self.sender.connection().flush_timeout
+    }
+
+    /// Set the time `flush` waits for the outgoing buffer to drain
+    pub fn set_flush_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.sender.connection().flush_timeout = timeout;
+        self
+    }
+
+    /// Return whether the backend currently holds an established connection
+    pub fn is_connected(&self) -> bool {
+        self.sender.connection().is_connected()
+    }
+
+    /// Return the number of bytes waiting in the outgoing buffer
+    pub fn buffered_bytes(&self) -> usize {
+        self.sender.connection().buffered_bytes()
+    }
+
+    /// Return the number of frames dropped due to a full outgoing buffer
+    pub fn dropped_messages(&self) -> u64 {
+        self.sender.connection().dropped()
+    }
+}
+
+impl Backend for UnixStreamBackend {
+    /// Log a message via the Unix stream socket.
+    ///
+    /// The message is appended to the outgoing buffer, see `TcpBackend::log_message`.
+    fn log_message(&self, msg: WireMessage) -> Result<()> {
+        let mut msg: Vec<u8> = msg.to_gelf()?.into();
+
+        // raw messages need to be terminated with a 0-byte
+        msg.push(0x00);
+
+        self.sender.send(msg)
+    }
+
+    /// Wait until all buffered frames are written to the socket
+    fn flush(&self) -> Result<()> {
+        self.sender.flush()
+    }
+}
+
+impl Connector for UnixConnector {
+    /// Connect to the socket, connecting to a Unix socket never blocks
+    fn connect(&self, _timeout: Duration) -> io::Result<Box<dyn Stream>> {
+        let stream = UnixStream::connect_addr(&self.destination)?;
+        stream.set_nonblocking(true)?;
+
+        Ok(Box::new(stream))
+    }
+}
+
+impl Stream for UnixStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        UnixStream::shutdown(self, net::Shutdown::Both)
+    }
+}
+
+/// Return the socket address of a filesystem path
+fn pathname_address(path: &Path) -> Result<SocketAddr> {
+    SocketAddr::from_pathname(path).map_err(|e| {
+        failure::Error::from(e)
+            .context(format!("Invalid Unix socket path '{}'", path.display()))
+            .context(Error::BackendCreationFailed)
+            .into()
+    })
+}
+
+/// Return the socket address of a name in the abstract namespace
+#[cfg(target_os = "linux")]
+fn abstract_address(name: &[u8]) -> Result<SocketAddr> {
+    SocketAddr::from_abstract_name(name).map_err(|e| {
+        failure::Error::from(e)
+            .context("Invalid abstract Unix socket name")
+            .context(Error::BackendCreationFailed)
+            .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::backends::test_util::{log, short_message};
+
+    static SOCKET_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    /// Return a unique socket path, removed when dropped
+    struct SocketPath(PathBuf);
+
+    impl SocketPath {
+        fn new() -> SocketPath {
+            let name = format!("gelf-test-{}-{}.sock", process::id(), SOCKET_COUNTER.fetch_add(1, Ordering::SeqCst));
+            SocketPath(std::env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for SocketPath {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    fn read_frames(stream: UnixStream, num: usize) -> Vec<String> {
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let mut frames = Vec::new();
+        let mut frame = Vec::new();
+
+        while frames.len() < num {
+            reader.read_until(0, &mut frame).expect("Failed to read frame");
+            assert_eq!(frame.pop(), Some(0), "Frame is not null-terminated");
+            frames.push(short_message(&frame));
+            frame.clear();
+        }
+
+        frames
+    }
+
+    #[test]
+    fn send_datagrams() {
+        let path = SocketPath::new();
+        let receiver = UnixDatagram::bind(&path.0).unwrap();
+
+        let mut backend = UnixDatagramBackend::new(&path.0).unwrap();
+        backend.set_compression(MessageCompression::None);
+        log(&backend, "datagram").unwrap();
+
+        let mut buffer = vec![0; 65536];
+        let size = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(short_message(&buffer[..size]), "datagram");
+    }
+
+    #[test]
+    fn chunk_large_datagrams() {
+        let path = SocketPath::new();
+        let receiver = UnixDatagram::bind(&path.0).unwrap();
+
+        let mut backend = UnixDatagramBackend::new_with_chunksize(&path.0, ChunkSize::Custom(100)).unwrap();
+        backend.set_compression(MessageCompression::None);
+        log(&backend, &"x".repeat(300)).unwrap();
+
+        let mut buffer = vec![0; 65536];
+        let size = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..2], b"\x1e\x0f");
+        assert_eq!(size, 112);
+        assert!(buffer[11] > 3, "Expected more than 3 chunks, got {}", buffer[11]);
+    }
+
+    #[test]
+    fn fail_on_missing_datagram_socket() {
+        let path = SocketPath::new();
+
+        let backend = UnixDatagramBackend::new(&path.0).unwrap();
+        assert!(log(&backend, "nobody listens").is_err());
+    }
+
+    #[test]
+    fn send_frames_to_stream_socket() {
+        let path = SocketPath::new();
+        let listener = UnixListener::bind(&path.0).unwrap();
+
+        let backend = UnixStreamBackend::new(&path.0).unwrap();
+        let (conn, _) = listener.accept().unwrap();
+
+        log(&backend, "first").unwrap();
+        log(&backend, "second").unwrap();
+        backend.flush().unwrap();
+
+        assert_eq!(read_frames(conn, 2), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn reconnect_to_restarted_stream_socket() {
+        let path = SocketPath::new();
+        let listener = UnixListener::bind(&path.0).unwrap();
+
+        let mut backend = UnixStreamBackend::new(&path.0).unwrap();
+        backend.set_reconnect_delay(Duration::from_millis(1), Duration::from_millis(10));
+
+        let (conn, _) = listener.accept().unwrap();
+        drop(conn);
+        drop(listener);
+        std::fs::remove_file(&path.0).unwrap();
+
+        log(&backend, "during").unwrap();
+        assert!(!backend.is_connected());
+
+        let listener = UnixListener::bind(&path.0).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        log(&backend, "after").unwrap();
+
+        let (conn, _) = listener.accept().unwrap();
+        assert_eq!(read_frames(conn, 2), vec!["during", "after"]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn use_abstract_namespace() {
+        let name = format!("gelf-test-{}-{}", process::id(), SOCKET_COUNTER.fetch_add(1, Ordering::SeqCst));
+
+        let receiver = UnixDatagram::bind_addr(&abstract_address(name.as_bytes()).unwrap()).unwrap();
+        let backend = UnixDatagramBackend::new_abstract(name.as_bytes(), ChunkSize::LAN).unwrap();
+        log(&backend, "abstract datagram").unwrap();
+
+        let mut buffer = vec![0; 65536];
+        let size = receiver.recv(&mut buffer).unwrap();
+        let mut decompressor = libdeflater::Decompressor::new();
+        let mut json = vec![0; 65536];
+        let json_size = decompressor.gzip_decompress(&buffer[..size], &mut json).unwrap();
+        assert_eq!(short_message(&json[..json_size]), "abstract datagram");
+
+        let stream_name = format!("{}-stream", name);
+        let listener = UnixListener::bind_addr(&abstract_address(stream_name.as_bytes()).unwrap()).unwrap();
+        let backend = UnixStreamBackend::new_abstract(stream_name.as_bytes()).unwrap();
+        let (conn, _) = listener.accept().unwrap();
+        log(&backend, "abstract stream").unwrap();
+        assert_eq!(read_frames(conn, 1), vec!["abstract stream"]);
+    }
+}
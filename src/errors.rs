@@ -1,6 +1,8 @@
-use failure;
-use std;
+// `failure`'s derive emits its impls inside an anonymous const
+#![allow(non_local_definitions)]
+
 use libdeflater::CompressionError as CompressedError;
+use std::{fmt, io};
 
 #[derive(Clone, Debug, Fail)]
 pub enum Error {
@@ -25,6 +27,20 @@ pub enum Error {
     IllegalChunkSize { size: u16 },
     #[fail(display = "Invalid compression level: {}", level)]
     InvalidCompressionLevel { level: i32 },
+    #[fail(display = "Invalid TLS configuration")]
+    InvalidTlsConfig,
+    #[fail(display = "'{}' is not a legal HTTP header", name)]
+    IllegalHttpHeader { name: String },
+    #[fail(display = "The GELF HTTP input responded with '{} {}'", status, reason)]
+    UnexpectedHttpStatus { status: u16, reason: String },
+    #[fail(display = "Failed to resolve the destination address")]
+    AddressResolutionFailed,
+    #[fail(display = "The destination {} refused the UDP packets (ICMP port unreachable)", address)]
+    DestinationRefused { address: std::net::SocketAddr },
+    #[fail(display = "Invalid pacing: {}", reason)]
+    InvalidPacing { reason: &'static str },
+    #[fail(display = "The message of {} bytes can't be truncated to {} bytes", size, limit)]
+    MessageTooLarge { size: usize, limit: usize },
 }
 
 #[derive(Clone, Debug)]
@@ -40,4 +56,100 @@ impl From<CompressedError> for CompressionError {
     }
 }
 
+/// FanoutError reports all children of a `FanoutBackend` which failed
+///
+/// The failures are given as pairs of the child's index and its error.
+#[derive(Debug)]
+pub struct FanoutError {
+    failures: Vec<(usize, failure::Error)>,
+}
+
+impl FanoutError {
+    /// Construct a new FanoutError from the failures of the children
+    pub(crate) fn new(failures: Vec<(usize, failure::Error)>) -> FanoutError {
+        FanoutError { failures }
+    }
+
+    /// Return the indices and errors of all failed children
+    pub fn failures(&self) -> &[(usize, failure::Error)] {
+        &self.failures
+    }
+
+    /// Return the indices of all failed children
+    pub fn failed_backends(&self) -> Vec<usize> {
+        self.failures.iter().map(|(index, _)| *index).collect()
+    }
+}
+
+impl fmt::Display for FanoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} fanout backend(s) failed:", self.failures.len())?;
+
+        for (index, error) in self.failures.iter() {
+            let causes: Vec<String> = error.iter_chain().map(|cause| cause.to_string()).collect();
+            write!(f, " [{}] {};", index, causes.join(": "))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl failure::Fail for FanoutError {}
+
+/// UdpSendError reports chunks a `UdpBackend` failed to send
+///
+/// Chunks which were skipped after an earlier chunk of the same message
+/// failed are not counted as attempted. The error of the first failed chunk
+/// is the cause.
+#[derive(Debug)]
+pub struct UdpSendError {
+    sent: usize,
+    attempted: usize,
+    total: usize,
+    error: io::Error,
+}
+
+impl UdpSendError {
+    /// Construct a new UdpSendError
+    pub(crate) fn new(sent: usize, attempted: usize, total: usize, error: io::Error) -> UdpSendError {
+        UdpSendError { sent, attempted, total, error }
+    }
+
+    /// Return the number of chunks which were sent
+    pub fn sent(&self) -> usize {
+        self.sent
+    }
+
+    /// Return the number of chunks which were attempted to send
+    pub fn attempted(&self) -> usize {
+        self.attempted
+    }
+
+    /// Return the number of chunks of all messages
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Return the error of the first failed chunk
+    pub fn io_error(&self) -> &io::Error {
+        &self.error
+    }
+}
+
+impl fmt::Display for UdpSendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Sent {} of {} attempted UDP chunks ({} in total): {}",
+            self.sent, self.attempted, self.total, self.error
+        )
+    }
+}
+
+impl failure::Fail for UdpSendError {
+    fn cause(&self) -> Option<&dyn failure::Fail> {
+        Some(&self.error)
+    }
+}
+
 pub type Result<T> = std::result::Result<T, failure::Error>;
@@ -71,10 +71,10 @@ impl Serialize for Level {
     }
 }
 
-impl Into<LogLevel> for Level {
+impl From<Level> for LogLevel {
     /// Allow for Into conversion to Rust's LogLevel
-    fn into(self) -> LogLevel {
-        self.to_rust()
+    fn from(level: Level) -> LogLevel {
+        level.to_rust()
     }
 }
 
@@ -85,16 +85,16 @@ impl From<LogLevel> for Level {
     }
 }
 
-impl Into<LogLevelFilter> for Level {
+impl From<Level> for LogLevelFilter {
     /// Allow for Into conversion from Rust's LogLevelFilter
-    fn into(self) -> LogLevelFilter {
-        self.to_rust().to_level_filter()
+    fn from(level: Level) -> LogLevelFilter {
+        level.to_rust().to_level_filter()
     }
 }
 
-impl Into<i8> for &Level {
-    fn into(self) -> i8 {
-        *self as i8
+impl From<&Level> for i8 {
+    fn from(level: &Level) -> i8 {
+        *level as i8
     }
 }
 
@@ -34,7 +34,7 @@
 //!     let mut logger = Logger::new(Box::new(backend))
 //!         .expect("Failed to determine hostname");
 //!     logger.set_default_metadata(String::from("facility"),
-//!          String::from("example-rust-app"));
+//!          String::from("example-rust-app")).unwrap();
 //!
 //!     // Create a (complex) message
 //!     let mut message = Message::new(String::from("Custom message!"));
@@ -92,6 +92,16 @@
 //!     }
 //! }
 //! ```
+//!
+//! # Features
+//!
+//! - `tls`: Encrypted connections with `TcpBackend::new_with_tls`, `TlsConfig` and
+//!   `https` URLs for the `HttpBackend`. It pulls in `rustls` and is therefore opt-in:
+//!
+//! ```toml
+//! [dependencies]
+//! gelf = { version = "0.5", features = ["tls"] }
+//! ```
 #![crate_type = "lib"]
 
 extern crate chrono;
@@ -120,8 +130,18 @@ mod logger;
 mod message;
 mod util;
 
-pub use backends::{Backend, NullBackend, TcpBackend, UdpBackend};
-pub use errors::{Error, Result};
+pub use backends::{
+    AsyncBackend, Backend, BalanceStrategy, BalancedBackend, CapturedMessage, FailoverBackend, FanoutBackend,
+    FileBackend, FileFraming, HttpBackend, IpPreference, MemoryBackend, MemoryHandle, NullBackend, OverflowPolicy,
+    Pacing, SizeRoutingBackend, SpoolingBackend, SyncPolicy, TcpBackend, UdpBackend, UdpBackendBuilder,
+};
+#[cfg(feature = "tls")]
+pub use backends::TlsConfig;
+#[cfg(unix)]
+pub use backends::{UnixDatagramBackend, UnixStreamBackend};
+pub use errors::{Error, FanoutError, Result, UdpSendError};
 pub use level::Level;
 pub use logger::Logger;
-pub use message::{ChunkSize, Message, MessageCompression, WireMessage};
+pub use message::{
+    ChunkIdStrategy, ChunkSize, Message, MessageCompression, MessageLimits, MetadataValue, WireMessage,
+};
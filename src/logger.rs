@@ -1,10 +1,9 @@
 use std::collections::HashMap;
 
-use hostname;
-use log;
 use log::set_boxed_logger;
-use crate::{Backend, Error, Message, WireMessage};
+use crate::{Backend, Error, Message, MessageLimits, WireMessage};
 use crate::errors::Result;
+use crate::message::{normalize_field_name, validate_field_name};
 
 /// Logger for sending log-messages
 ///
@@ -13,11 +12,18 @@ use crate::errors::Result;
 ///
 /// By default all encountered errors will be silently ignored. If you want the logger
 /// to panic when an error occurs, you can change the behaviour with `Logger::enable_panic_on_error`.
+///
+/// Additional field names which violate the GELF spec are rejected by default. With
+/// `Logger::enable_field_name_normalization` they are rewritten into legal names instead.
+///
+/// Messages are not limited in size by default, see `Logger::set_message_limits`.
 pub struct Logger {
     hostname: String,
     backend: Box<dyn Backend>,
     default_metadata: HashMap<String, String>,
     panic_on_error: bool,
+    normalize_field_names: bool,
+    message_limits: MessageLimits,
 }
 
 impl Logger {
@@ -42,9 +48,11 @@ impl Logger {
     pub fn new_with_hostname(backend: Box<dyn Backend>, hostname: &str) -> Logger {
         Logger {
             hostname: String::from(hostname),
-            backend: backend,
+            backend,
             default_metadata: HashMap::new(),
             panic_on_error: false,
+            normalize_field_names: false,
+            message_limits: MessageLimits::default(),
         }
     }
 
@@ -66,12 +74,26 @@ impl Logger {
     /// Log a message via the logger's transport to a GELF server.
     ///
     /// The logger will automatically add `default_metadata` fields to the message
-    /// if missing in the passed `Message`.
-    pub fn log_message(&self, msg: Message) {
-        let result = self.backend.log_message(WireMessage::new(msg, &self));
+    /// if missing in the passed `Message`. Illegal additional field names are
+    /// rewritten if field name normalization is enabled. Otherwise the message
+    /// is rejected with `Error::IllegalNameForAdditional`. Finally the message is
+    /// truncated to the `message_limits`.
+    pub fn log_message(&self, mut msg: Message) {
+        let result = if self.normalize_field_names {
+            msg.normalize_metadata_names();
+            Ok(())
+        } else {
+            msg.validate_metadata_names()
+        }.and_then(|_| {
+            let mut msg = WireMessage::new(msg, self);
+            msg.apply_limits(&self.message_limits)?;
+            self.backend.log_message(msg)
+        });
 
-        if result.is_err() && self.panic_on_error {
-            panic!(result.unwrap_err());
+        if let Err(e) = result {
+            if self.panic_on_error {
+                panic!("{}", e);
+            }
         }
     }
 
@@ -103,22 +125,35 @@ impl Logger {
     /// # use gelf::{Logger, NullBackend, Message};
     /// # let backend = NullBackend::new();
     /// # let mut logger = Logger::new(Box::new(backend)).unwrap();
-    /// logger.set_default_metadata(String::from("facility"), String::from("my_awesome_rust_service"));
+    /// logger.set_default_metadata(String::from("facility"), String::from("my_awesome_rust_service"))
+    ///     .unwrap();
     ///
     /// logger.log_message(Message::new(String::from("This is important information")));
     /// // -> The message will contain an additional field "_facility" with the value "my_awesome_rust_service"
     /// ```
+    ///
+    /// The key is checked like a `Message`'s metadata key: If field name normalization
+    /// is enabled an illegal key is rewritten, otherwise `Error::IllegalNameForAdditional`
+    /// is returned.
     pub fn set_default_metadata<S, T>(
         &mut self,
         key: S,
         value: T
-    ) -> &mut Self
+    ) -> Result<&mut Self>
     where
         S: Into<String>,
         T: Into<String>
     {
-        self.default_metadata.insert(key.into(), value.into());
-        self
+        let mut key = key.into();
+
+        if self.normalize_field_names {
+            key = normalize_field_name(&key).into_owned();
+        } else {
+            validate_field_name(&key)?;
+        }
+
+        self.default_metadata.insert(key, value.into());
+        Ok(self)
     }
 
     /// Return a flag whether the logger panics when it encounters an error
@@ -137,6 +172,40 @@ impl Logger {
         self.panic_on_error = false;
         self
     }
+
+    /// Return a flag whether the logger rewrites illegal additional field names
+    pub fn field_name_normalization(&self) -> bool {
+        self.normalize_field_names
+    }
+
+    /// Rewrite illegal additional field names instead of rejecting them
+    ///
+    /// Every character not allowed by the GELF spec is replaced with an underscore,
+    /// e.g. `user id` becomes `user_id`.
+    pub fn enable_field_name_normalization(&mut self) -> &mut Self {
+        self.normalize_field_names = true;
+        self
+    }
+
+    /// Reject messages and default metadata with illegal additional field names
+    pub fn disable_field_name_normalization(&mut self) -> &mut Self {
+        self.normalize_field_names = false;
+        self
+    }
+
+    /// Return the limits messages are truncated to
+    pub fn message_limits(&self) -> &MessageLimits {
+        &self.message_limits
+    }
+
+    /// Set the limits messages are truncated to
+    ///
+    /// Oversized messages otherwise fail to be chunked or are rejected by
+    /// the GELF server, e.g. Elasticsearch doesn't index terms above 32766 bytes.
+    pub fn set_message_limits(&mut self, limits: MessageLimits) -> &mut Self {
+        self.message_limits = limits;
+        self
+    }
 }
 
 impl log::Log for Logger {
@@ -155,11 +224,102 @@ impl log::Log for Logger {
     /// for more details
     fn log(&self, record: &log::Record) {
         if !self.enabled(record.metadata()) {
-            ()
+            return;
         }
 
         self.log_message(From::from(record))
     }
 
-    fn flush(&self) {}
+    /// Flushes messages buffered by the backend.
+    fn flush(&self) {
+        let result = self.backend.flush();
+
+        if let Err(e) = result {
+            if self.panic_on_error {
+                panic!("{}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryBackend, NullBackend};
+
+    #[test]
+    fn default_metadata_rejects_illegal_names() {
+        let mut logger = Logger::new_with_hostname(Box::new(NullBackend::new()), "localhost");
+
+        assert!(logger.set_default_metadata("user id", "value").is_err());
+        assert!(logger.set_default_metadata("id", "value").is_err());
+        assert!(logger.set_default_metadata("facility", "value").is_ok());
+        assert_eq!(logger.default_metadata().len(), 1);
+    }
+
+    #[test]
+    fn default_metadata_normalizes_illegal_names() {
+        let mut logger = Logger::new_with_hostname(Box::new(NullBackend::new()), "localhost");
+        logger.enable_field_name_normalization();
+
+        logger.set_default_metadata("user id", "value").unwrap();
+
+        assert_eq!(logger.default_metadata().get("user_id"), Some(&String::from("value")));
+        assert!(logger.default_metadata().get("user id").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "not a legal name")]
+    fn log_message_rejects_illegal_names() {
+        let mut logger = Logger::new_with_hostname(Box::new(NullBackend::new()), "localhost");
+        logger.enable_panic_on_error();
+
+        let message: Message = serde_json::from_str(r#"{"short_message": "foo", "level": 1, "_user id": 1}"#).unwrap();
+        logger.log_message(message);
+    }
+
+    #[test]
+    fn log_message_normalizes_illegal_names() {
+        let backend = MemoryBackend::new();
+        let messages = backend.handle();
+
+        let mut logger = Logger::new_with_hostname(Box::new(backend), "localhost");
+        logger.enable_panic_on_error().enable_field_name_normalization();
+
+        let message: Message = serde_json::from_str(r#"{"short_message": "foo", "level": 1, "_user id": 1}"#).unwrap();
+        logger.log_message(message);
+
+        let normalized = messages.assert_logged("the normalized message", |msg| msg.short_message() == "foo");
+        assert_eq!(normalized.metadata("user_id").and_then(|value| value.as_i64()), Some(1));
+        assert!(normalized.metadata("user id").is_none());
+    }
+
+    #[test]
+    fn log_message_applies_limits() {
+        let backend = MemoryBackend::new();
+        let messages = backend.handle();
+
+        let mut logger = Logger::new_with_hostname(Box::new(backend), "localhost");
+        logger.enable_panic_on_error().set_message_limits(MessageLimits {
+            max_field_length: Some(16),
+            max_total_size: Some(512),
+            ..MessageLimits::default()
+        });
+        logger.set_default_metadata("facility", "service").unwrap();
+
+        let mut message = Message::new("short");
+        message.set_full_message("full ".repeat(1000))
+            .set_metadata("path", "x".repeat(100)).unwrap();
+        logger.log_message(message);
+        logger.log_message(Message::new("untouched"));
+
+        let truncated = messages.assert_logged("the truncated message", |msg| msg.short_message() == "short");
+        assert!(truncated.json().len() <= 512);
+        assert!(truncated.full_message().unwrap().ends_with("…[truncated]"));
+        assert_eq!(truncated.metadata("path").and_then(|value| value.as_str()).map(str::len), Some(16));
+        assert_eq!(truncated.metadata("gelf_truncated").and_then(|value| value.as_bool()), Some(true));
+
+        let untouched = messages.assert_logged("the untouched message", |msg| msg.short_message() == "untouched");
+        assert!(untouched.metadata("gelf_truncated").is_none());
+    }
 }
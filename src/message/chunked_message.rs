@@ -1,11 +1,16 @@
 use std::cmp;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
 
-use rand;
 use crate::Error;
 use crate::Result;
+use crate::util;
 
 /// Overhead per chunk is 12 bytes: magic(2) + id(8) + pos(1) + total (1)
-const CHUNK_OVERHEAD: u8 = 12;
+pub(crate) const CHUNK_OVERHEAD: u8 = 12;
 
 /// Chunk-size for LANs
 const CHUNK_SIZE_LAN: u16 = 8154;
@@ -14,16 +19,33 @@ const CHUNK_SIZE_LAN: u16 = 8154;
 const CHUNK_SIZE_WAN: u16 = 1420;
 
 /// Magic bytes identifying a GELF message chunk
-static MAGIC_BYTES: &'static [u8; 2] = b"\x1e\x0f";
+static MAGIC_BYTES: &[u8; 2] = b"\x1e\x0f";
+
+/// Size of the IPv4 header without options
+const IPV4_HEADER_SIZE: u32 = 20;
+
+/// Size of the IPv6 header without extension headers
+const IPV6_HEADER_SIZE: u32 = 40;
+
+/// Largest IPv4 packet, or IPv6 payload without jumbograms
+const MAX_IP_LENGTH: u32 = 65535;
+
+/// Size of the UDP header
+const UDP_HEADER_SIZE: u32 = 8;
 
 /// ChunkSize is a value type representing the size of a message-chunk
 ///
 /// It provides default sizes for WANs and LANs
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ChunkSize {
     LAN,
     WAN,
     Custom(u16),
+    /// Derive the size from the MTU of the route to the destination
+    ///
+    /// `UdpBackend` looks up the MTU on Linux and falls back to `WAN` if that
+    /// fails. Everywhere else this is the same as `WAN`.
+    Auto,
 }
 
 impl ChunkSize {
@@ -31,10 +53,109 @@ impl ChunkSize {
     pub fn size(self) -> u16 {
         match self {
             ChunkSize::LAN => CHUNK_SIZE_LAN,
-            ChunkSize::WAN => CHUNK_SIZE_WAN,
+            ChunkSize::WAN | ChunkSize::Auto => CHUNK_SIZE_WAN,
             ChunkSize::Custom(size) => size,
         }
     }
+
+    /// Return the largest chunk-size whose packets fit the MTU without fragmentation
+    ///
+    /// The size is capped at the largest UDP datagram the IP version can
+    /// carry. Returns None if the MTU is too small to carry any payload.
+    pub fn from_mtu(mtu: u32, ipv6: bool) -> Option<ChunkSize> {
+        // The IPv6 length field excludes the header, the IPv4 one includes it
+        let (ip_header_size, max_packet_size) = if ipv6 {
+            (IPV6_HEADER_SIZE, IPV6_HEADER_SIZE + MAX_IP_LENGTH)
+        } else {
+            (IPV4_HEADER_SIZE, MAX_IP_LENGTH)
+        };
+        let size = cmp::min(mtu, max_packet_size)
+            .checked_sub(ip_header_size + UDP_HEADER_SIZE + u32::from(CHUNK_OVERHEAD))?;
+
+        match size {
+            0 => None,
+            size => Some(ChunkSize::Custom(size as u16)),
+        }
+    }
+}
+
+/// ChunkIdStrategy defines how the IDs of chunked messages are generated
+///
+/// The ID identifies the chunks belonging to the same message, so it must be
+/// unique among all messages the receiver assembles at the same time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChunkIdStrategy {
+    /// Use random IDs
+    #[default]
+    Random,
+    /// Derive IDs from the current time and a hash of the host, as the GELF spec recommends
+    ///
+    /// A sequence number starting at a random value distinguishes the
+    /// messages sent in the same millisecond.
+    TimeHostHash,
+    /// Use a counter shared by the whole process, starting at a random value
+    Counter,
+    /// Use a counter starting at the given value, which yields reproducible IDs
+    CounterFrom(u64),
+}
+
+/// ChunkIdGenerator generates the IDs of chunked messages with a `ChunkIdStrategy`
+pub struct ChunkIdGenerator {
+    strategy: ChunkIdStrategy,
+    counter: AtomicU64,
+}
+
+impl ChunkIdGenerator {
+    /// Construct a new ChunkIdGenerator
+    pub fn new(strategy: ChunkIdStrategy) -> ChunkIdGenerator {
+        let start = match strategy {
+            ChunkIdStrategy::CounterFrom(start) => start,
+            // Generators in the same process must not produce the same sequence
+            ChunkIdStrategy::TimeHostHash => rand::random(),
+            _ => 0,
+        };
+
+        ChunkIdGenerator {
+            strategy,
+            counter: AtomicU64::new(start),
+        }
+    }
+
+    /// Return the strategy of the generator
+    pub fn strategy(&self) -> ChunkIdStrategy {
+        self.strategy
+    }
+
+    /// Generate the ID of the next message sent by `host`
+    pub fn next_id(&self, host: &str) -> u64 {
+        match self.strategy {
+            ChunkIdStrategy::Random => rand::thread_rng().gen(),
+            ChunkIdStrategy::TimeHostHash => {
+                // The sequence number distinguishes messages sent in the same millisecond
+                let millis = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_millis() as u64)
+                    .unwrap_or_default();
+                let sequence = self.counter.fetch_add(1, Ordering::Relaxed) & 0xffff;
+
+                (millis << 16 | sequence) ^ util::hash(host.as_bytes())
+            }
+            ChunkIdStrategy::Counter => process_counter().fetch_add(1, Ordering::Relaxed),
+            ChunkIdStrategy::CounterFrom(_) => self.counter.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for ChunkIdGenerator {
+    fn default() -> ChunkIdGenerator {
+        ChunkIdGenerator::new(ChunkIdStrategy::default())
+    }
+}
+
+/// Return the chunk ID counter of the process
+fn process_counter() -> &'static AtomicU64 {
+    static COUNTER: OnceLock<AtomicU64> = OnceLock::new();
+    COUNTER.get_or_init(|| AtomicU64::new(rand::random()))
 }
 
 /// ChunkedMessage is an internal type for chunking an already serialized `WireMessage`
@@ -52,25 +173,30 @@ impl ChunkedMessage {
     /// - chunk_size must be greater than 0
     /// - GELF allows for a maximum of 128 chunks per message
     pub fn new(chunk_size: ChunkSize, message: Vec<u8>) -> Result<ChunkedMessage> {
+        Self::new_with_id(chunk_size, message, rand::thread_rng().gen())
+    }
+
+    /// Construct a new ChunkedMessage with the given ID
+    pub fn new_with_id(chunk_size: ChunkSize, message: Vec<u8>, id: u64) -> Result<ChunkedMessage> {
         if chunk_size.size() == 0 {
             return Err(Error::IllegalChunkSize {
                 size: chunk_size.size(),
             }.into());
         }
 
-        // Ceiled integer division with (a + b - 1) / b
-        // Calculate with 64bit integers to avoid overflow - maybe replace with checked_*?
+        // Ceiled integer division
+        // Calculate with 64bit integers to avoid overflow
         let size = chunk_size.size() as u64;
-        let num_chunks = (message.len() as u64 + size as u64 - 1) / size;
+        let num_chunks = (message.len() as u64).div_ceil(size);
 
         if num_chunks > 128 {
             return Err(format_err!("Number of chunks exceeds 128, which the the maximum number of chunks in GELF. Check your chunk_size").context(Error::ChunkMessageFailed).into());
         }
 
         Ok(ChunkedMessage {
-            chunk_size: chunk_size,
+            chunk_size,
             payload: message,
-            id: ChunkedMessageId::random(),
+            id: ChunkedMessageId::from_int(id),
             num_chunks: num_chunks as u8,
         })
     }
@@ -84,10 +210,56 @@ impl ChunkedMessage {
         }
     }
 
+    /// Return the number of chunks of the message
+    pub fn num_chunks(&self) -> u8 {
+        self.num_chunks
+    }
+
     /// Return an iterator over all chunks of the message
-    pub fn iter(&self) -> ChunkedMessageIterator {
+    pub fn iter(&self) -> ChunkedMessageIterator<'_> {
         ChunkedMessageIterator::new(self)
     }
+
+    /// Append the headers of all chunks to the buffer
+    ///
+    /// Every header is `CHUNK_OVERHEAD` bytes long. Nothing is appended if the
+    /// message fits into a single chunk, since no header is required then.
+    pub fn write_headers(&self, buf: &mut Vec<u8>) {
+        if self.num_chunks > 1 {
+            for chunk_num in 0..self.num_chunks {
+                self.write_header(chunk_num, buf);
+            }
+        }
+    }
+
+    /// Return the part of the payload sent with the chunk
+    pub fn chunk_payload(&self, chunk_num: u8) -> &[u8] {
+        let chunk_size = self.chunk_size.size();
+        let slice_start = cmp::min(
+            (chunk_num as u32 * chunk_size as u32) as usize,
+            self.payload.len(),
+        );
+        let slice_end = cmp::min(
+            slice_start + chunk_size as usize,
+            self.payload.len(),
+        );
+
+        &self.payload[slice_start..slice_end]
+    }
+
+    /// Append the header of a chunk to the buffer
+    fn write_header(&self, chunk_num: u8, buf: &mut Vec<u8>) {
+        // Chunk binary layout:
+        //  2 bytes (magic bytes)
+        //  8 bytes (message id)
+        //  1 byte  (chunk number)
+        //  1 byte  (total amount of chunks in this message)
+        //  n bytes (chunk payload)
+        buf.extend(MAGIC_BYTES.iter());
+        buf.extend(self.id.as_bytes());
+        buf.push(chunk_num);
+        buf.push(self.num_chunks);
+    }
 }
 
 /// An iterator over all a chunked message's chunks
@@ -100,7 +272,7 @@ pub struct ChunkedMessageIterator<'a> {
 
 impl<'a> ChunkedMessageIterator<'a> {
     /// Create a new ChunkedMessageIterator
-    fn new(msg: &'a ChunkedMessage) -> ChunkedMessageIterator {
+    fn new(msg: &'a ChunkedMessage) -> ChunkedMessageIterator<'a> {
         ChunkedMessageIterator {
             message: msg,
             chunk_num: 0,
@@ -117,31 +289,15 @@ impl<'a> Iterator for ChunkedMessageIterator<'a> {
             return None;
         }
 
-        let mut chunk = Vec::new();
-
-        // Set the chunks boundaries
-        let chunk_size = self.message.chunk_size.size();
-        let slice_start = (self.chunk_num as u32 * chunk_size as u32) as usize;
-        let slice_end = cmp::min(
-            slice_start + chunk_size as usize,
-            self.message.payload.len(),
-        );
+        let payload = self.message.chunk_payload(self.chunk_num);
+        let mut chunk = Vec::with_capacity(payload.len() + CHUNK_OVERHEAD as usize);
 
         // The chunk header is only required when the message size exceeds one chunk
         if self.message.num_chunks > 1 {
-            // Chunk binary layout:
-            //  2 bytes (magic bytes)
-            //  8 bytes (message id)
-            //  1 byte  (chunk number)
-            //  1 byte  (total amount of chunks in this message)
-            //  n bytes (chunk payload)
-            chunk.extend(MAGIC_BYTES.iter());
-            chunk.extend(self.message.id.as_bytes());
-            chunk.push(self.chunk_num);
-            chunk.push(self.message.num_chunks);
+            self.message.write_header(self.chunk_num, &mut chunk);
         }
 
-        chunk.extend(self.message.payload[slice_start..slice_end].iter());
+        chunk.extend_from_slice(payload);
 
         self.chunk_num += 1;
 
@@ -156,18 +312,7 @@ impl<'a> Iterator for ChunkedMessageIterator<'a> {
 struct ChunkedMessageId([u8; 8]);
 
 #[allow(dead_code)]
-impl<'a> ChunkedMessageId {
-    /// Create a new, random ChunkedMessageId.
-    fn random() -> ChunkedMessageId {
-        let mut bytes = [0; 8];
-
-        for b in 0..8 {
-            bytes[b] = rand::random();
-        }
-
-        return ChunkedMessageId::from_bytes(bytes);
-    }
-
+impl ChunkedMessageId {
     /// Create a new ChunkedMessageId from a 64 int.
     fn from_int(mut id: u64) -> ChunkedMessageId {
         let mut bytes = [0; 8];
@@ -213,7 +358,7 @@ mod tests {
         ];
 
         for raw_id in raw_ids {
-            let id = ChunkedMessageId::from_bytes(raw_id.clone());
+            let id = ChunkedMessageId::from_bytes(*raw_id);
             assert_eq!(id.as_bytes(), raw_id);
         }
     }
@@ -252,7 +397,7 @@ mod tests {
         assert_eq!(msg_1_chunk.len(), 1);
         assert_eq!(msg_2_chunks.len() as u32, 2 + 2 * CHUNK_OVERHEAD as u32);
         assert_eq!(
-            msg_128_chunks.len() as u64,
+            msg_128_chunks.len(),
             128 + 128 * (CHUNK_OVERHEAD as u64)
         );
     }
@@ -268,6 +413,41 @@ mod tests {
         assert!(msg1.id.to_int() != msg3.id.to_int());
     }
 
+    #[test]
+    fn generate_chunk_ids_with_counter() {
+        let generator = ChunkIdGenerator::new(ChunkIdStrategy::CounterFrom(41));
+        assert_eq!(generator.next_id("host"), 41);
+        assert_eq!(generator.next_id("other-host"), 42);
+
+        let msg = ChunkedMessage::new_with_id(ChunkSize::Custom(1), get_data(2), 0x0102030405060708).unwrap();
+        assert_eq!(&msg.iter().next().unwrap()[2..10], b"\x01\x02\x03\x04\x05\x06\x07\x08");
+
+        let generator = ChunkIdGenerator::new(ChunkIdStrategy::Counter);
+        let first = generator.next_id("host");
+        assert_eq!(generator.next_id("host"), first.wrapping_add(1));
+    }
+
+    #[test]
+    fn generate_chunk_ids_from_time_and_host() {
+        let generator = ChunkIdGenerator::new(ChunkIdStrategy::TimeHostHash);
+
+        let ids: Vec<u64> = (0..1000).map(|_| generator.next_id("host")).collect();
+        let mut unique = ids.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), ids.len());
+
+        let generator = ChunkIdGenerator::new(ChunkIdStrategy::TimeHostHash);
+        assert_ne!(generator.next_id("host"), ChunkIdGenerator::new(ChunkIdStrategy::TimeHostHash).next_id("other-host"));
+
+        // Generators of the same host start at different sequence numbers
+        let mut first_ids: Vec<u64> = (0..8)
+            .map(|_| ChunkIdGenerator::new(ChunkIdStrategy::TimeHostHash).next_id("host") & 0xffff)
+            .collect();
+        first_ids.dedup();
+        assert!(first_ids.len() > 1);
+    }
+
     #[test]
     fn chunk_message_correct_math() {
         let msg1 = ChunkedMessage::new(ChunkSize::Custom(3), get_data(1)).unwrap();
@@ -313,12 +493,44 @@ mod tests {
 
     fn chunking(chunk_size: u16, msg_size: u32) {
         check_chunks(
-            chunk_size as u16,
+            chunk_size,
             msg_size,
             (msg_size / chunk_size as u32) as u8 + 1,
         );
     }
 
+    #[test]
+    fn chunk_headers_and_payloads_match_chunks() {
+        let msg = ChunkedMessage::new(ChunkSize::Custom(33), get_data(100)).unwrap();
+        let mut headers = Vec::new();
+        msg.write_headers(&mut headers);
+        assert_eq!(headers.len(), 4 * CHUNK_OVERHEAD as usize);
+
+        for (chunk_num, chunk) in msg.iter().enumerate() {
+            let header = &headers[chunk_num * CHUNK_OVERHEAD as usize..][..CHUNK_OVERHEAD as usize];
+            assert_eq!(&chunk[..CHUNK_OVERHEAD as usize], header);
+            assert_eq!(&chunk[CHUNK_OVERHEAD as usize..], msg.chunk_payload(chunk_num as u8));
+        }
+
+        let single = ChunkedMessage::new(ChunkSize::Custom(100), get_data(100)).unwrap();
+        let mut headers = Vec::new();
+        single.write_headers(&mut headers);
+        assert!(headers.is_empty());
+        assert_eq!(single.chunk_payload(0), &get_data(100)[..]);
+    }
+
+    #[test]
+    fn chunk_size_from_mtu() {
+        assert_eq!(ChunkSize::from_mtu(1500, false), Some(ChunkSize::Custom(1460)));
+        assert_eq!(ChunkSize::from_mtu(1500, true), Some(ChunkSize::Custom(1440)));
+        assert_eq!(ChunkSize::from_mtu(65536, false), Some(ChunkSize::Custom(65495)));
+        assert_eq!(ChunkSize::from_mtu(65536, true), Some(ChunkSize::Custom(65476)));
+        assert_eq!(ChunkSize::from_mtu(u32::MAX, true), Some(ChunkSize::Custom(65515)));
+        assert_eq!(ChunkSize::from_mtu(40, false), None);
+        assert_eq!(ChunkSize::from_mtu(0, true), None);
+        assert_eq!(ChunkSize::Auto.size(), CHUNK_SIZE_WAN);
+    }
+
     #[test]
     #[should_panic]
     fn test_illegal_chunk_size() {
@@ -337,7 +549,7 @@ mod tests {
     fn check_chunks(chunk_size: u16, msg_size: u32, expected_chunk_count: u8) {
         let msg_data = get_data(msg_size as usize);
         let msg_data_clone = msg_data.clone();
-        let msg = ChunkedMessage::new(ChunkSize::Custom(chunk_size as u16), msg_data).unwrap();
+        let msg = ChunkedMessage::new(ChunkSize::Custom(chunk_size), msg_data).unwrap();
         let mut counter: u8 = 0;
         for chunk in msg.iter() {
             println!("{:?}", chunk);
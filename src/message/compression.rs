@@ -1,5 +1,4 @@
 use libdeflater::{CompressionLvl, Compressor};
-use std::iter;
 use std::collections::HashMap;
 use std::cell::RefCell;
 
@@ -9,7 +8,7 @@ use crate::errors::Result;
 thread_local!(static COMPRESSORS: RefCell<DeflaterCompressor> = RefCell::new(DeflaterCompressor::new()));
 
 /// MessageCompression represents all possible compression algorithms in GELF.
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum MessageCompression {
     None,
     Gzip {
@@ -20,26 +19,31 @@ pub enum MessageCompression {
     },
 }
 
-impl MessageCompression {
+impl Default for MessageCompression {
     /// Return the default compression algorithm.
-    pub fn default() -> MessageCompression {
+    fn default() -> MessageCompression {
         MessageCompression::Gzip {level: 1}
     }
+}
 
+impl MessageCompression {
     /// Compress a serialized message with the defined algorithm.
     pub fn compress(self, message: &WireMessage) -> Result<Vec<u8>> {
-        let json = message.to_gelf()?;
+        message.to_compressed_gelf(self)
+    }
 
+    /// Compress arbitrary data with the defined algorithm.
+    pub(crate) fn compress_bytes(self, data: Vec<u8>) -> Result<Vec<u8>> {
         Ok(match self {
-            MessageCompression::None => json.into_bytes(),
+            MessageCompression::None => data,
             MessageCompression::Gzip {level} => {
                 COMPRESSORS.with(|compressor| {
                     compressor.borrow_mut().with(level, |compressor| {
-                        let bound = compressor.gzip_compress_bound(json.as_bytes().len());
+                        let bound = compressor.gzip_compress_bound(data.len());
 
-                        let mut buffer: Vec<u8> = iter::repeat(0 ).take(bound).collect();
+                        let mut buffer: Vec<u8> = vec![0; bound];
 
-                        compressor.gzip_compress(json.as_bytes(), buffer.as_mut_slice())
+                        compressor.gzip_compress(&data, buffer.as_mut_slice())
                             .map_err(|err| {
                                 Error::CompressMessageFailed {
                                     compression_method: "gzip",
@@ -55,11 +59,11 @@ impl MessageCompression {
             MessageCompression::Zlib {level} => {
                 COMPRESSORS.with(|compressor| {
                     compressor.borrow_mut().with(level, |compressor| {
-                        let bound = compressor.zlib_compress_bound(json.as_bytes().len());
+                        let bound = compressor.zlib_compress_bound(data.len());
 
-                        let mut buffer: Vec<u8> = iter::repeat(0 ).take(bound).collect();
+                        let mut buffer: Vec<u8> = vec![0; bound];
 
-                        compressor.zlib_compress(json.as_bytes(), buffer.as_mut_slice())
+                        compressor.zlib_compress(&data, buffer.as_mut_slice())
                             .map_err(|err| {
                                 Error::CompressMessageFailed {
                                     compression_method: "zlib",
@@ -143,7 +147,7 @@ mod test {
 
             let actual = compressor.compress(&message).expect("Should success");
 
-            let mut buffer: Vec<u8> = iter::repeat(0).take(serde_json::to_vec(&message).unwrap().len()).collect();
+            let mut buffer: Vec<u8> = vec![0; serde_json::to_vec(&message).unwrap().len()];
 
             let decoded = decompressor.gzip_decompress(actual.as_slice(), buffer.as_mut_slice()).expect("Should not throw an error");
 
@@ -173,7 +177,7 @@ mod test {
 
             let actual = compressor.compress(&message).expect("Should success");
 
-            let mut buffer: Vec<u8> = iter::repeat(0).take(serde_json::to_vec(&message).unwrap().len()).collect();
+            let mut buffer: Vec<u8> = vec![0; serde_json::to_vec(&message).unwrap().len()];
 
             let decoded = decompressor.zlib_decompress(actual.as_slice(), buffer.as_mut_slice()).expect("Should not throw an error");
 
@@ -207,9 +211,9 @@ mod test {
 
                         let mut decompressor = libdeflater::Decompressor::new();
 
-                        let actual = compressor.clone().compress(&message).expect("Should success");
+                        let actual = compressor.compress(&message).expect("Should success");
 
-                        let mut buffer: Vec<u8> = iter::repeat(0).take(serde_json::to_vec(&message).unwrap().len()).collect();
+                        let mut buffer: Vec<u8> = vec![0; serde_json::to_vec(&message).unwrap().len()];
 
                         let decoded = decompressor.zlib_decompress(actual.as_slice(), buffer.as_mut_slice()).expect("Should not throw an error");
 
@@ -246,9 +250,9 @@ mod test {
 
                         let mut decompressor = libdeflater::Decompressor::new();
 
-                        let actual = compressor.clone().compress(&message).expect("Should success");
+                        let actual = compressor.compress(&message).expect("Should success");
 
-                        let mut buffer: Vec<u8> = iter::repeat(0).take(serde_json::to_vec(&message).unwrap().len()).collect();
+                        let mut buffer: Vec<u8> = vec![0; serde_json::to_vec(&message).unwrap().len()];
 
                         let decoded = decompressor.gzip_decompress(actual.as_slice(), buffer.as_mut_slice()).expect("Should not throw an error");
 
@@ -0,0 +1,74 @@
+use std::borrow::Cow;
+
+use crate::Error;
+use crate::errors::Result;
+
+/// Return whether a character is allowed in an additional field's name
+///
+/// The GELF spec defines legal names with the regex `^[\w\.\-]*$`, where
+/// `\w` only covers ASCII word characters.
+fn is_legal_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-'
+}
+
+/// Check whether `name` is a legal name for an additional GELF field
+///
+/// Besides the spec's character restrictions the name must not be empty and
+/// must not be `id`, since `_id` is reserved by Graylog.
+pub fn validate_field_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "id" || !name.chars().all(is_legal_char) {
+        return Err(Error::IllegalNameForAdditional { name: name.into() }.into());
+    }
+
+    Ok(())
+}
+
+/// Rewrite `name` into a legal name for an additional GELF field
+///
+/// Every illegal character is replaced with an underscore (e.g. `user id`
+/// becomes `user_id`). The reserved name `id` is rewritten to `id_` and an
+/// empty name to `_`. Legal names are returned unchanged.
+pub fn normalize_field_name(name: &str) -> Cow<'_, str> {
+    if validate_field_name(name).is_ok() {
+        return Cow::Borrowed(name);
+    }
+
+    match name {
+        "" => Cow::Borrowed("_"),
+        "id" => Cow::Borrowed("id_"),
+        _ => Cow::Owned(
+            name.chars()
+                .map(|c| if is_legal_char(c) { c } else { '_' })
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legal_field_names() {
+        for name in &["foo", "foo_bar", "foo.bar", "foo-bar", "Foo123", "_", "ID", "identifier"] {
+            assert!(validate_field_name(name).is_ok(), "'{}' should be legal", name);
+        }
+    }
+
+    #[test]
+    fn illegal_field_names() {
+        for name in &["", "id", "user id", "foo/bar", "föö", "foo:bar", "tab\tbed"] {
+            assert!(validate_field_name(name).is_err(), "'{}' should be illegal", name);
+        }
+    }
+
+    #[test]
+    fn normalize_field_names() {
+        assert_eq!(normalize_field_name("user_id"), "user_id");
+        assert_eq!(normalize_field_name("user id"), "user_id");
+        assert_eq!(normalize_field_name("foo/bar:baz"), "foo_bar_baz");
+        assert_eq!(normalize_field_name("föö"), "f__");
+        assert_eq!(normalize_field_name("id"), "id_");
+        assert_eq!(normalize_field_name(""), "_");
+    }
+}
@@ -0,0 +1,374 @@
+use std::borrow::Cow;
+use std::io;
+
+use crate::{Message, MetadataValue};
+
+/// Additional field marking a message whose content was cut by its limits
+pub(crate) const TRUNCATED_FIELD: &str = "gelf_truncated";
+
+/// Serialized length of the `full_message` key with its separators
+const FULL_MESSAGE_KEY_LEN: usize = r#","full_message":"#.len();
+
+/// MessageLimits bound the size of the messages sent by a `Logger`
+///
+/// Lengths are measured in bytes. A part exceeding its limit is truncated at
+/// a character boundary and ends with the `marker`; surplus additional fields
+/// are dropped. Messages which were cut get the field `_gelf_truncated: true`,
+/// which isn't counted by `max_fields`.
+///
+/// If a message exceeds `max_total_size` after all other limits were applied,
+/// its `full_message`, its largest additional fields and finally its
+/// `short_message` are cut until it fits. A message which still doesn't fit,
+/// e.g. because of a long `host`, is rejected with `Error::MessageTooLarge`.
+///
+/// ```
+/// # use gelf::{Logger, MessageLimits, NullBackend};
+/// # let mut logger = Logger::new_with_hostname(Box::new(NullBackend::new()), "localhost");
+/// logger.set_message_limits(MessageLimits {
+///     max_field_length: Some(32766),
+///     max_fields: Some(100),
+///     max_total_size: Some(1024 * 1024),
+///     ..MessageLimits::default()
+/// });
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageLimits {
+    /// Maximum length of the `short_message`
+    pub max_short_message_length: Option<usize>,
+    /// Maximum length of the `full_message`
+    pub max_full_message_length: Option<usize>,
+    /// Maximum length of every string value of an additional field
+    pub max_field_length: Option<usize>,
+    /// Maximum number of additional fields
+    pub max_fields: Option<usize>,
+    /// Maximum length of the serialized GELF/JSON message
+    pub max_total_size: Option<usize>,
+    /// Text appended to truncated parts
+    pub marker: Cow<'static, str>,
+}
+
+impl Default for MessageLimits {
+    fn default() -> MessageLimits {
+        MessageLimits {
+            max_short_message_length: None,
+            max_full_message_length: None,
+            max_field_length: None,
+            max_fields: None,
+            max_total_size: None,
+            marker: Cow::Borrowed("…[truncated]"),
+        }
+    }
+}
+
+impl MessageLimits {
+    /// Apply the length and field limits to the message
+    ///
+    /// Returns whether anything was cut.
+    pub(crate) fn truncate_parts(&self, msg: &mut Message) -> bool {
+        let mut truncated = false;
+
+        if let Some(max) = self.max_short_message_length {
+            truncated |= self.truncate(&mut msg.short_message, max);
+        }
+
+        if let (Some(max), Some(full_message)) = (self.max_full_message_length, msg.full_message.as_mut()) {
+            truncated |= self.truncate(full_message, max);
+        }
+
+        if let Some(max) = self.max_fields {
+            let mut keys: Vec<Cow<str>> = msg.metadata
+                .keys()
+                .filter(|key| *key != TRUNCATED_FIELD)
+                .cloned()
+                .collect();
+
+            if keys.len() > max {
+                // Sorted for a stable choice of the dropped fields
+                keys.sort();
+                for key in &keys[max..] {
+                    msg.metadata.remove(key);
+                }
+                truncated = true;
+            }
+        }
+
+        if let Some(max) = self.max_field_length {
+            for value in msg.metadata.values_mut() {
+                if let MetadataValue::String(value) = value {
+                    truncated |= self.truncate(value, max);
+                }
+            }
+        }
+
+        truncated
+    }
+
+    /// Cut the message to reduce its serialized length by `excess` bytes
+    ///
+    /// The `full_message` is cut first, then the largest additional fields and
+    /// finally the `short_message`. Every field is measured once, so this is
+    /// linear in the size of the message. Escaped characters may leave the
+    /// message slightly above the target, so the caller measures again.
+    ///
+    /// Returns false if nothing is left to cut.
+    pub(crate) fn shrink(&self, msg: &mut Message, mut excess: usize) -> bool {
+        let mut cut = false;
+
+        if let Some(full_message) = msg.full_message.as_mut() {
+            if self.shrink_value(full_message, excess) {
+                return true;
+            }
+
+            excess = excess.saturating_sub(FULL_MESSAGE_KEY_LEN + json_len(full_message));
+            msg.full_message = None;
+            cut = true;
+        }
+
+        if excess > 0 {
+            // Quotes, underscore prefix, colon and comma of every field
+            let mut fields: Vec<(usize, Cow<str>)> = msg.metadata
+                .iter()
+                .filter(|(key, _)| *key != TRUNCATED_FIELD)
+                .map(|(key, value)| (json_len(key) + json_len(value) + 3, key.clone()))
+                .collect();
+            fields.sort_unstable_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+            for (size, key) in fields {
+                cut = true;
+
+                if let Some(MetadataValue::String(value)) = msg.metadata.get_mut(&key) {
+                    if self.shrink_value(value, excess) {
+                        return true;
+                    }
+                }
+
+                msg.metadata.remove(&key);
+                excess = excess.saturating_sub(size);
+                if excess == 0 {
+                    break;
+                }
+            }
+        }
+
+        if excess > 0 && !msg.short_message.is_empty() {
+            let max = msg.short_message.len().saturating_sub(excess);
+            self.truncate(&mut msg.short_message, max);
+            cut = true;
+        }
+
+        cut
+    }
+
+    /// Cut `excess` bytes from the value, returns false if it should be dropped instead
+    fn shrink_value(&self, value: &mut Cow<str>, excess: usize) -> bool {
+        let max = value.len().saturating_sub(excess);
+        if max <= self.marker.len() {
+            return false;
+        }
+
+        self.truncate(value, max);
+        true
+    }
+
+    /// Truncate the value to `max` bytes including the marker
+    ///
+    /// Values shorter than the marker are truncated without it.
+    fn truncate(&self, value: &mut Cow<str>, max: usize) -> bool {
+        if value.len() <= max {
+            return false;
+        }
+
+        let marker = if self.marker.len() <= max { &*self.marker } else { "" };
+        let end = floor_char_boundary(value, max - marker.len());
+
+        let mut truncated = String::with_capacity(end + marker.len());
+        truncated.push_str(&value[..end]);
+        truncated.push_str(marker);
+        *value = Cow::Owned(truncated);
+
+        true
+    }
+}
+
+/// Return the length of the value serialized to JSON
+pub(crate) fn serialized_len<T: serde::Serialize + ?Sized>(value: &T) -> serde_json::Result<usize> {
+    let mut counter = Counter(0);
+    serde_json::to_writer(&mut counter, value)?;
+
+    Ok(counter.0)
+}
+
+/// Return the serialized length of a value which can't fail to serialize
+fn json_len<T: serde::Serialize + ?Sized>(value: &T) -> usize {
+    serialized_len(value).unwrap_or_default()
+}
+
+/// A writer only counting the bytes written to it
+struct Counter(usize);
+
+impl io::Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Return the largest character boundary not above `index`
+fn floor_char_boundary(value: &str, index: usize) -> usize {
+    (0..=index.min(value.len()))
+        .rev()
+        .find(|&i| value.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> MessageLimits {
+        MessageLimits {
+            marker: Cow::Borrowed("..."),
+            ..MessageLimits::default()
+        }
+    }
+
+    #[test]
+    fn truncate_messages_at_char_boundaries() {
+        let limits = MessageLimits {
+            max_short_message_length: Some(8),
+            max_full_message_length: Some(10),
+            ..limits()
+        };
+
+        let mut msg = Message::new("Grüße aus Köln");
+        msg.set_full_message("short");
+        assert!(limits.truncate_parts(&mut msg));
+
+        // "Grü" is 4 bytes, the 'ß' doesn't fit before the marker
+        assert_eq!(msg.short_message(), "Grü...");
+        assert_eq!(msg.full_message().as_deref(), Some("short"));
+    }
+
+    #[test]
+    fn truncate_field_values() {
+        let limits = MessageLimits {
+            max_field_length: Some(10),
+            ..limits()
+        };
+
+        let mut msg = Message::new("foo");
+        msg.set_metadata("path", "/a/very/long/path").unwrap()
+            .set_metadata("status", 200).unwrap();
+        assert!(limits.truncate_parts(&mut msg));
+
+        assert_eq!(msg.metadata("path").and_then(MetadataValue::as_str), Some("/a/very..."));
+        assert_eq!(msg.metadata("status").and_then(MetadataValue::as_i64), Some(200));
+    }
+
+    #[test]
+    fn drop_surplus_fields() {
+        let limits = MessageLimits {
+            max_fields: Some(2),
+            ..limits()
+        };
+
+        let mut msg = Message::new("foo");
+        for key in &["c", "a", "d", "b"] {
+            msg.set_metadata(*key, 1).unwrap();
+        }
+        assert!(limits.truncate_parts(&mut msg));
+
+        let mut keys: Vec<&str> = msg.all_metadata().keys().map(|key| key.as_ref()).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn keep_messages_within_limits() {
+        let limits = MessageLimits {
+            max_short_message_length: Some(3),
+            max_fields: Some(1),
+            max_field_length: Some(3),
+            ..limits()
+        };
+
+        let mut msg = Message::new("foo");
+        msg.set_metadata("a", "bar").unwrap();
+        let original = msg.clone();
+
+        assert!(!limits.truncate_parts(&mut msg));
+        assert_eq!(msg, original);
+    }
+
+    #[test]
+    fn shrink_full_message_first() {
+        let limits = limits();
+
+        let mut msg = Message::new("foo");
+        msg.set_full_message("x".repeat(20))
+            .set_metadata("a", "y".repeat(20)).unwrap();
+
+        assert!(limits.shrink(&mut msg, 10));
+        assert_eq!(msg.full_message().as_deref(), Some("xxxxxxx..."));
+        assert_eq!(msg.metadata("a").and_then(MetadataValue::as_str).map(str::len), Some(20));
+    }
+
+    #[test]
+    fn shrink_largest_fields_in_one_pass() {
+        let limits = limits();
+
+        let mut msg = Message::new("foo");
+        msg.set_full_message("x".repeat(20))
+            .set_metadata("small", 1).unwrap()
+            .set_metadata("large", "y".repeat(40)).unwrap()
+            .set_metadata("medium", "z".repeat(20)).unwrap();
+
+        // The full message and the large field go, the medium field is cut
+        let excess = FULL_MESSAGE_KEY_LEN + 22 + r#""_large":"","#.len() + 40 + 5;
+        assert!(limits.shrink(&mut msg, excess));
+
+        assert!(msg.full_message().is_none());
+        assert!(msg.metadata("large").is_none());
+        assert_eq!(msg.metadata("medium").and_then(MetadataValue::as_str), Some("zzzzzzzzzzzz..."));
+        assert_eq!(msg.metadata("small").and_then(MetadataValue::as_i64), Some(1));
+        assert_eq!(msg.short_message(), "foo");
+    }
+
+    #[test]
+    fn shrink_short_message_last() {
+        let limits = limits();
+
+        let mut msg = Message::new("foo");
+        msg.set_metadata("a", 1).unwrap();
+
+        // The field is 7 bytes, one more comes from the short message
+        assert!(limits.shrink(&mut msg, 8));
+        assert!(msg.metadata("a").is_none());
+        assert_eq!(msg.short_message(), "fo");
+
+        assert!(limits.shrink(&mut msg, 2));
+        assert_eq!(msg.short_message(), "");
+        assert!(!limits.shrink(&mut msg, 1));
+    }
+
+    #[test]
+    fn reject_messages_which_cannot_fit() {
+        let logger = crate::Logger::new_with_hostname(Box::new(crate::NullBackend::new()), "localhost");
+        let limits = MessageLimits {
+            max_total_size: Some(40),
+            ..limits()
+        };
+
+        let mut msg = crate::WireMessage::new(Message::new("foo"), &logger);
+        let err = msg.apply_limits(&limits).unwrap_err();
+
+        match err.downcast_ref::<crate::Error>() {
+            Some(crate::Error::MessageTooLarge { limit, .. }) => assert_eq!(*limit, 40),
+            _ => panic!("Unexpected error: {}", err),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// MetadataValue is the typed value of an additional GELF field
+///
+/// GELF allows additional fields to be either strings or numbers. Booleans are
+/// not part of the spec, but are accepted and indexed natively by Graylog.
+/// Every variant is serialized to its native JSON type, so numeric fields can
+/// be used for range queries and aggregations.
+///
+/// Integers are stored as `i64`. Unsigned values beyond `i64::MAX` are stored
+/// as a lossy `MetadataValue::Float`. JSON has no NaN or infinity, so
+/// `Message::set_metadata` stores such floats as a `MetadataValue::String`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MetadataValue<'a> {
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(Cow<'a, str>),
+}
+
+impl<'a> MetadataValue<'a> {
+    /// Return the value as a string slice if it is a `MetadataValue::String`
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MetadataValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Return the value as an i64 if it is a `MetadataValue::Integer`
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            MetadataValue::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Return the value as an f64 if it is numeric
+    ///
+    /// Integers are converted lossy to floating point.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            MetadataValue::Integer(value) => Some(*value as f64),
+            MetadataValue::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Return the value as a bool if it is a `MetadataValue::Boolean`
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            MetadataValue::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Replace a NaN or infinite float with its string representation
+    ///
+    /// serde_json would write them as `null`, which can't be parsed back.
+    pub(crate) fn into_json_compatible(self) -> Self {
+        match self {
+            MetadataValue::Float(value) if !value.is_finite() => MetadataValue::String(Cow::Owned(value.to_string())),
+            value => value,
+        }
+    }
+
+    /// Convert the value into one which owns all its data
+    pub fn into_owned(self) -> MetadataValue<'static> {
+        match self {
+            MetadataValue::Boolean(value) => MetadataValue::Boolean(value),
+            MetadataValue::Integer(value) => MetadataValue::Integer(value),
+            MetadataValue::Float(value) => MetadataValue::Float(value),
+            MetadataValue::String(value) => MetadataValue::String(Cow::Owned(value.into_owned())),
+        }
+    }
+}
+
+impl<'a> fmt::Display for MetadataValue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MetadataValue::Boolean(value) => value.fmt(f),
+            MetadataValue::Integer(value) => value.fmt(f),
+            MetadataValue::Float(value) => value.fmt(f),
+            MetadataValue::String(value) => value.fmt(f),
+        }
+    }
+}
+
+impl<'a> From<Cow<'a, str>> for MetadataValue<'a> {
+    fn from(value: Cow<'a, str>) -> Self {
+        MetadataValue::String(value)
+    }
+}
+
+impl<'a> From<&'a str> for MetadataValue<'a> {
+    fn from(value: &'a str) -> Self {
+        MetadataValue::String(Cow::Borrowed(value))
+    }
+}
+
+impl<'a> From<&'a String> for MetadataValue<'a> {
+    fn from(value: &'a String) -> Self {
+        MetadataValue::String(Cow::Borrowed(value.as_str()))
+    }
+}
+
+impl<'a> From<String> for MetadataValue<'a> {
+    fn from(value: String) -> Self {
+        MetadataValue::String(Cow::Owned(value))
+    }
+}
+
+impl<'a> From<bool> for MetadataValue<'a> {
+    fn from(value: bool) -> Self {
+        MetadataValue::Boolean(value)
+    }
+}
+
+impl<'a> From<f32> for MetadataValue<'a> {
+    fn from(value: f32) -> Self {
+        MetadataValue::Float(value as f64)
+    }
+}
+
+impl<'a> From<f64> for MetadataValue<'a> {
+    fn from(value: f64) -> Self {
+        MetadataValue::Float(value)
+    }
+}
+
+macro_rules! impl_from_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl<'a> From<$ty> for MetadataValue<'a> {
+                fn from(value: $ty) -> Self {
+                    MetadataValue::Integer(value as i64)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_integer!(i8, i16, i32, i64, isize, u8, u16, u32);
+
+macro_rules! impl_from_unsigned_integer {
+    ($($ty:ty),*) => {
+        $(
+            /// Values beyond `i64::MAX` become a lossy `MetadataValue::Float`
+            impl<'a> From<$ty> for MetadataValue<'a> {
+                fn from(value: $ty) -> Self {
+                    match i64::try_from(value) {
+                        Ok(value) => MetadataValue::Integer(value),
+                        Err(_) => MetadataValue::Float(value as f64),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_unsigned_integer!(u64, usize);
+
+impl<'a> PartialEq<str> for MetadataValue<'a> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == Some(other)
+    }
+}
+
+impl<'a, 'b> PartialEq<&'b str> for MetadataValue<'a> {
+    fn eq(&self, other: &&'b str) -> bool {
+        self.as_str() == Some(*other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_native_json_types() {
+        assert_eq!(json!(true), serde_json::to_value(MetadataValue::from(true)).unwrap());
+        assert_eq!(json!(42), serde_json::to_value(MetadataValue::from(42)).unwrap());
+        assert_eq!(json!(1.5), serde_json::to_value(MetadataValue::from(1.5)).unwrap());
+        assert_eq!(json!("foo"), serde_json::to_value(MetadataValue::from("foo")).unwrap());
+    }
+
+    #[test]
+    fn convert_wide_integers() {
+        assert_eq!(MetadataValue::from(vec![1, 2, 3].len()), MetadataValue::Integer(3));
+        assert_eq!(MetadataValue::from(-7isize), MetadataValue::Integer(-7));
+        assert_eq!(MetadataValue::from(i64::MAX as u64), MetadataValue::Integer(i64::MAX));
+        assert_eq!(MetadataValue::from(u64::MAX), MetadataValue::Float(u64::MAX as f64));
+    }
+
+    #[test]
+    fn deserialize_native_json_types() {
+        let values: Vec<MetadataValue> = serde_json::from_str(r#"[true, -42, 1.5, "foo", 1e3]"#).unwrap();
+
+        assert_eq!(values[0], MetadataValue::Boolean(true));
+        assert_eq!(values[1], MetadataValue::Integer(-42));
+        assert_eq!(values[2], MetadataValue::Float(1.5));
+        assert_eq!(values[3], MetadataValue::String("foo".into()));
+        assert_eq!(values[4], MetadataValue::Float(1000.0));
+    }
+}
@@ -1,13 +1,16 @@
 use std::collections::HashMap;
 use std::borrow::Cow;
-use chrono::{DateTime, Utc, NaiveDateTime};
-use log;
+use chrono::{DateTime, TimeZone, Utc};
 
-pub use self::chunked_message::{ChunkSize, ChunkedMessage};
+pub use self::chunked_message::{ChunkIdGenerator, ChunkIdStrategy, ChunkSize, ChunkedMessage};
+pub(crate) use self::chunked_message::CHUNK_OVERHEAD;
 pub use self::compression::MessageCompression;
+pub(crate) use self::field_name::{normalize_field_name, validate_field_name};
+pub use self::limits::MessageLimits;
+pub use self::metadata_value::MetadataValue;
 pub use self::wire_message::WireMessage;
 
-use crate::{Level, util, Error};
+use crate::{Level, util};
 use crate::errors::Result;
 use serde::de;
 use serde::de::Deserialize;
@@ -15,6 +18,9 @@ use serde_with::with_prefix;
 
 mod chunked_message;
 mod compression;
+mod field_name;
+mod limits;
+mod metadata_value;
 mod wire_message;
 
 /// Message is thre representation of a GELF message.
@@ -29,11 +35,11 @@ mod wire_message;
 pub struct Message<'a> {
     short_message: Cow<'a, str>,
     full_message: Option<Cow<'a, str>>,
-    #[serde(deserialize_with = "parse_unix_seconds")]
+    #[serde(default, deserialize_with = "parse_unix_seconds")]
     timestamp: Option<DateTime<Utc>>,
     level: Level,
     #[serde(flatten, with = "prefix_metadata")]
-    metadata: HashMap<Cow<'a, str>, Cow<'a, str>>,
+    metadata: HashMap<Cow<'a, str>, MetadataValue<'a>>,
 }
 
 impl<'a> Message<'a> {
@@ -139,16 +145,35 @@ impl<'a> Message<'a> {
     }
 
     /// Return a metadata field with given key
-    pub fn metadata(&self, key: &'a str) -> Option<&Cow<'a, str>> {
+    pub fn metadata(&self, key: &str) -> Option<&MetadataValue<'a>> {
         self.metadata.get(key)
     }
 
     /// Return all metadata
-    pub fn all_metadata(&self) -> &HashMap<Cow<'a, str>, Cow<'a, str>> {
+    pub fn all_metadata(&self) -> &HashMap<Cow<'a, str>, MetadataValue<'a>> {
         &self.metadata
     }
 
     /// Set a metadata field with given key to value
+    ///
+    /// The key needs to be a legal GELF field name (matching `^[\w\.\-]*$`)
+    /// and must not be `id`. Otherwise an `Error::IllegalNameForAdditional`
+    /// is returned.
+    ///
+    /// The value can be anything convertible into a `MetadataValue`, e.g.
+    /// strings, integers, floats and booleans. Each is sent with its
+    /// native JSON type. NaN and infinite floats, which JSON can't represent,
+    /// are sent as the strings `NaN`, `inf` and `-inf`:
+    ///
+    /// ```
+    /// # use gelf::Message;
+    /// let mut message = Message::new("Request handled");
+    /// message
+    ///     .set_metadata("path", "/index.html").unwrap()
+    ///     .set_metadata("status", 200).unwrap()
+    ///     .set_metadata("duration", 0.042).unwrap()
+    ///     .set_metadata("cached", false).unwrap();
+    /// ```
     pub fn set_metadata<S, T>(
         &mut self,
         key: S,
@@ -156,18 +181,59 @@ impl<'a> Message<'a> {
     ) -> Result<&mut Self>
         where
             S: Into<Cow<'a, str>> + AsRef<str>,
-            T: Into<Cow<'a, str>> + AsRef<str>,
+            T: Into<MetadataValue<'a>>,
     {
-        let key = key.into();
-
-        if key == "id" {
-            return Err(Error::IllegalNameForAdditional { name: key.into() }.into());
-        }
+        validate_field_name(key.as_ref())?;
 
-        self.metadata.insert(key, value.into());
+        self.metadata.insert(key.into(), value.into().into_json_compatible());
 
         Ok(self)
     }
+
+    /// Check all metadata keys for legal GELF field names
+    ///
+    /// Keys are validated by `set_metadata` already, but messages deserialized
+    /// from GELF/JSON may contain arbitrary keys.
+    pub(crate) fn validate_metadata_names(&self) -> Result<()> {
+        self.metadata
+            .keys()
+            .try_for_each(|key| validate_field_name(key))
+    }
+
+    /// Rewrite all illegal metadata keys into legal GELF field names
+    ///
+    /// If a rewritten key collides with an existing one, the existing value wins.
+    /// If several illegal keys are rewritten to the same key, the value of the
+    /// lexicographically smallest original key wins.
+    pub(crate) fn normalize_metadata_names(&mut self) {
+        let mut illegal_keys: Vec<Cow<'a, str>> = self.metadata
+            .keys()
+            .filter(|key| validate_field_name(key).is_err())
+            .cloned()
+            .collect();
+        illegal_keys.sort();
+
+        for key in illegal_keys {
+            if let Some(value) = self.metadata.remove(&key) {
+                let key = normalize_field_name(&key).into_owned();
+                self.metadata.entry(Cow::Owned(key)).or_insert(value);
+            }
+        }
+    }
+
+    /// Convert the message into one which owns all its data
+    pub fn into_owned(self) -> Message<'static> {
+        Message {
+            short_message: Cow::Owned(self.short_message.into_owned()),
+            full_message: self.full_message.map(|msg| Cow::Owned(msg.into_owned())),
+            timestamp: self.timestamp,
+            level: self.level,
+            metadata: self.metadata
+                .into_iter()
+                .map(|(key, value)| (Cow::Owned(key.into_owned()), value.into_owned()))
+                .collect(),
+        }
+    }
 }
 
 impl<'a> From<&'a log::Record<'a>> for Message<'a> {
@@ -208,9 +274,8 @@ fn parse_unix_seconds<'de, D>(d: D) -> std::result::Result<Option<DateTime<Utc>>
 
     let seconds = value.trunc() as i64;
     let nsecs = (value.fract() * 1_000_000_000_f64).abs() as u32;
-    let ndt = NaiveDateTime::from_timestamp_opt(seconds, nsecs);
-    if let Some(ndt) = ndt {
-        Ok(Some(DateTime::<Utc>::from_utc(ndt, Utc)))
+    if let Some(datetime) = Utc.timestamp_opt(seconds, nsecs).single() {
+        Ok(Some(datetime))
     } else {
         Err(de::Error::custom(format!(
             "Invalid or out of range value '{}' for DateTime",
@@ -317,7 +382,7 @@ mod test {
 
         let mut actual_parsed: Vec<Message> = vec![];
 
-        while let Some(m) = stream.next() {
+        for m in stream.by_ref() {
             actual_parsed.push(m.unwrap());
         }
 
@@ -325,6 +390,112 @@ mod test {
         assert_eq!(stream.byte_offset(), input.len());
     }
 
+    #[test]
+    fn test_typed_metadata_roundtrip() {
+        let mut message = Message::new("typed");
+        message
+            .set_metadata("string", "value").unwrap()
+            .set_metadata("integer", 42).unwrap()
+            .set_metadata("float", 0.5).unwrap()
+            .set_metadata("boolean", true).unwrap();
+
+        let input = serde_json::to_string(&message).unwrap();
+        let actual_message: Message = serde_json::from_str(input.as_str()).expect("Parse with success");
+
+        assert_eq!(actual_message, message);
+        assert_eq!(actual_message.metadata("string"), Some(&MetadataValue::String("value".into())));
+        assert_eq!(actual_message.metadata("integer"), Some(&MetadataValue::Integer(42)));
+        assert_eq!(actual_message.metadata("float"), Some(&MetadataValue::Float(0.5)));
+        assert_eq!(actual_message.metadata("boolean"), Some(&MetadataValue::Boolean(true)));
+    }
+
+    #[test]
+    fn test_non_finite_metadata_roundtrip() {
+        let mut message = Message::new("non-finite");
+        message
+            .set_metadata("nan", f64::NAN).unwrap()
+            .set_metadata("inf", f64::INFINITY).unwrap()
+            .set_metadata("neg_inf", f32::NEG_INFINITY).unwrap();
+
+        let input = serde_json::to_string(&message).unwrap();
+        let actual_message: Message = serde_json::from_str(input.as_str()).expect("Parse with success");
+
+        assert_eq!(actual_message, message);
+        assert_eq!(actual_message.metadata("nan"), Some(&MetadataValue::String("NaN".into())));
+        assert_eq!(actual_message.metadata("inf"), Some(&MetadataValue::String("inf".into())));
+        assert_eq!(actual_message.metadata("neg_inf"), Some(&MetadataValue::String("-inf".into())));
+    }
+
+    #[test]
+    fn test_parse_numeric_additionals_json() {
+        let raw_message = r#"
+        {"version": "1.1",
+        "host": "example.org",
+        "short_message": "A short message",
+        "level": 1,
+        "_user_id": 9001,
+        "_latency": 12.5,
+        "_some_info": "foo"}
+        "#;
+
+        let actual_message: Message = serde_json::from_str(raw_message).expect("Parse with success");
+
+        assert_eq!(actual_message.metadata("user_id").and_then(MetadataValue::as_i64), Some(9001));
+        assert_eq!(actual_message.metadata("latency").and_then(MetadataValue::as_f64), Some(12.5));
+        assert_eq!(actual_message.metadata("some_info").expect("some info"), "foo");
+    }
+
+    #[test]
+    fn test_set_metadata_rejects_illegal_names() {
+        let mut message = Message::new("illegal");
+
+        assert!(message.set_metadata("id", "value").is_err());
+        assert!(message.set_metadata("user id", "value").is_err());
+        assert!(message.set_metadata("foo/bar", "value").is_err());
+        assert!(message.set_metadata("user_id", "value").is_ok());
+        assert_eq!(message.all_metadata().len(), 1);
+    }
+
+    #[test]
+    fn test_normalize_metadata_names() {
+        let raw_message = r#"
+        {"short_message": "A short message",
+        "level": 1,
+        "_user id": 1,
+        "_user_id": 2,
+        "_request/path": "/",
+        "_id": "reserved"}
+        "#;
+
+        let mut message: Message = serde_json::from_str(raw_message).expect("Parse with success");
+        assert!(message.validate_metadata_names().is_err());
+
+        message.normalize_metadata_names();
+        assert!(message.validate_metadata_names().is_ok());
+
+        assert_eq!(message.metadata("user_id"), Some(&MetadataValue::Integer(2)));
+        assert_eq!(message.metadata("request_path").expect("request path"), "/");
+        assert_eq!(message.metadata("id_").expect("id"), "reserved");
+        assert_eq!(message.all_metadata().len(), 3);
+    }
+
+    #[test]
+    fn test_normalize_colliding_metadata_names() {
+        let raw_message = r#"
+        {"short_message": "A short message",
+        "level": 1,
+        "_user/id": 3,
+        "_user:id": 2,
+        "_user id": 1}
+        "#;
+
+        let mut message: Message = serde_json::from_str(raw_message).expect("Parse with success");
+        message.normalize_metadata_names();
+
+        assert_eq!(message.metadata("user_id"), Some(&MetadataValue::Integer(1)));
+        assert_eq!(message.all_metadata().len(), 1);
+    }
+
     #[test]
     fn test_parse_timestamp_json() {
         let raw_message = r#"
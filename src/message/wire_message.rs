@@ -1,12 +1,13 @@
-use failure;
-use serde;
+use failure::Fail;
 use serde::ser::SerializeMap;
-use serde_json;
+use std::borrow::Cow;
 use std::collections::HashMap;
-use crate::{Message, Logger, MessageCompression, ChunkSize};
+use std::sync::{Arc, Mutex, OnceLock};
+use crate::{Message, Logger, MessageCompression, MessageLimits, ChunkSize};
 use crate::errors::Result;
 use crate::errors::Error;
 use crate::message::ChunkedMessage;
+use crate::message::limits::{serialized_len, TRUNCATED_FIELD};
 
 /// WireMessage is the representation of a fully assembled GELF message
 ///
@@ -16,9 +17,21 @@ use crate::message::ChunkedMessage;
 ///
 /// A WireMessage can be serialized to GELF/JSON (with and without compression)
 /// and is the abstraction passed to the transportation backends.
+///
+/// The serialized and compressed forms are cached and shared with all clones
+/// of the message, so backends receiving the same message don't repeat the work.
+#[derive(Clone, Debug)]
 pub struct WireMessage<'a> {
-    host: &'a str,
+    host: Cow<'a, str>,
     message: Message<'a>,
+    cache: Arc<SerializationCache>,
+}
+
+/// The serialized forms of a WireMessage
+#[derive(Debug, Default)]
+struct SerializationCache {
+    gelf: OnceLock<String>,
+    compressed: Mutex<Vec<(MessageCompression, Vec<u8>)>>,
 }
 
 impl<'a> WireMessage<'a> {
@@ -40,23 +53,142 @@ impl<'a> WireMessage<'a> {
         }
 
         WireMessage {
-            host: logger.hostname(),
+            host: Cow::Borrowed(logger.hostname()),
             message: msg,
+            cache: Default::default(),
+        }
+    }
+
+    /// Return the `host` the message is sent from
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Return the message
+    pub fn message(&self) -> &Message<'a> {
+        &self.message
+    }
+
+    /// Convert the message into one which owns all its data
+    ///
+    /// An owned message is no longer bound to the lifetime of the `Logger` and
+    /// can be sent to other threads.
+    pub fn into_owned(self) -> WireMessage<'static> {
+        WireMessage {
+            host: Cow::Owned(self.host.into_owned()),
+            message: self.message.into_owned(),
+            cache: self.cache,
+        }
+    }
+
+    /// Return whether both messages share their serialized and compressed forms
+    #[cfg(test)]
+    pub(crate) fn shares_cache_with(&self, other: &WireMessage) -> bool {
+        Arc::ptr_eq(&self.cache, &other.cache)
+    }
+
+    /// Return the number of cached compressed forms
+    #[cfg(test)]
+    pub(crate) fn compressed_forms(&self) -> usize {
+        self.cache.compressed.lock().unwrap().len()
+    }
+
+    /// Parse a GELF/JSON string produced by `to_gelf`
+    pub(crate) fn from_gelf(json: &str) -> Result<WireMessage<'static>> {
+        let parse_error = |e: serde_json::Error| -> failure::Error {
+            failure::Error::from(e)
+                .context("Failed to parse GELF json")
+                .context(Error::LogTransmitFailed)
+                .into()
+        };
+
+        let mut value: serde_json::Value = serde_json::from_str(json).map_err(parse_error)?;
+        let host = match value.get_mut("host").map(serde_json::Value::take) {
+            Some(serde_json::Value::String(host)) => host,
+            _ => return Err(format_err!("Missing host in GELF json").context(Error::LogTransmitFailed).into()),
+        };
+
+        Ok(WireMessage {
+            host: Cow::Owned(host),
+            message: serde_json::from_value(value).map_err(parse_error)?,
+            cache: Default::default(),
+        })
+    }
+
+    /// Truncate the message to the limits
+    ///
+    /// Must be applied before the message is serialized, as the cached forms
+    /// are not updated. Fails with `Error::MessageTooLarge` if the message
+    /// can't be cut to `max_total_size`.
+    pub(crate) fn apply_limits(&mut self, limits: &MessageLimits) -> Result<()> {
+        let mut truncated = limits.truncate_parts(&mut self.message);
+        if truncated {
+            self.mark_truncated();
+        }
+
+        let limit = match limits.max_total_size {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        loop {
+            let size = serialized_len(&*self)
+                .map_err(|e| failure::Error::from(e).context(Error::SerializeMessageFailed))?;
+
+            if size <= limit {
+                return Ok(());
+            }
+
+            // The marker field counts against the limit as well
+            if !truncated {
+                truncated = true;
+                self.mark_truncated();
+                continue;
+            }
+
+            if !limits.shrink(&mut self.message, size - limit) {
+                return Err(Error::MessageTooLarge { size, limit }.into());
+            }
         }
     }
 
+    /// Add the field marking a truncated message
+    fn mark_truncated(&mut self) {
+        self.message.metadata.insert(Cow::Borrowed(TRUNCATED_FIELD), true.into());
+    }
+
     /// Return a GELF/JSON string of this message
     pub fn to_gelf(&self) -> Result<String> {
-        serde_json::to_string(self).map_err(|e| {
+        if let Some(gelf) = self.cache.gelf.get() {
+            return Ok(gelf.clone());
+        }
+
+        let gelf = serde_json::to_string(self).map_err(|e| {
             failure::Error::from(e)
                 .context(Error::SerializeMessageFailed)
-                .into()
-        })
+        })?;
+
+        Ok(self.cache.gelf.get_or_init(|| gelf).clone())
     }
 
     /// Return a compressed GELF/JSON string of this message
     pub fn to_compressed_gelf(&self, compression: MessageCompression) -> Result<Vec<u8>> {
-        compression.compress(&self)
+        if compression == MessageCompression::None {
+            return Ok(self.to_gelf()?.into_bytes());
+        }
+
+        let cached = self.cache.compressed.lock().unwrap().iter()
+            .find(|(method, _)| *method == compression)
+            .map(|(_, compressed)| compressed.clone());
+
+        if let Some(compressed) = cached {
+            return Ok(compressed);
+        }
+
+        let compressed = compression.compress_bytes(self.to_gelf()?.into_bytes())?;
+        self.cache.compressed.lock().unwrap().push((compression, compressed.clone()));
+
+        Ok(compressed)
     }
 
     /// Serialize the messages and prepare it for chunking
@@ -81,7 +213,7 @@ impl<'a> serde::Serialize for WireMessage<'a> {
         map.serialize_value("1.1")?;
 
         map.serialize_key("host")?;
-        map.serialize_value(self.host)?;
+        map.serialize_value(&self.host)?;
 
         map.serialize_key("short_message")?;
         map.serialize_value(&self.message.short_message())?;
@@ -124,15 +256,19 @@ mod tests {
         let mut message = Message::new_with_level("short", Level::Alert);
         message.set_full_message("full");
 
-        let datetime = Utc.ymd(2000, 1, 1).and_hms_micro(1, 2, 3, 12_345);
+        let datetime = Utc.with_ymd_and_hms(2000, 1, 1, 1, 2, 3).unwrap() + chrono::Duration::microseconds(12_345);
         message.set_timestamp(datetime);
 
         message.set_metadata("key1", "value1").unwrap();
         message.set_metadata("key2", "value2").unwrap();
+        message.set_metadata("key3", 3).unwrap();
+        message.set_metadata("key4", 4.5).unwrap();
+        message.set_metadata("key5", true).unwrap();
 
         let wire_msg = WireMessage {
-            host: "host_value",
+            host: "host_value".into(),
             message,
+            cache: Default::default(),
         };
 
         let json = serde_json::to_value(wire_msg).expect("Failed to serialize WireMessage");
@@ -152,5 +288,49 @@ mod tests {
 
         assert_eq!(Some(json!("value1")), json.get("_key1").cloned());
         assert_eq!(Some(json!("value2")), json.get("_key2").cloned());
+        assert_eq!(Some(json!(3)), json.get("_key3").cloned());
+        assert_eq!(Some(json!(4.5)), json.get("_key4").cloned());
+        assert_eq!(Some(json!(true)), json.get("_key5").cloned());
+    }
+
+    #[test]
+    fn wire_message_roundtrip() {
+        let mut message = Message::new_with_level("short", Level::Error);
+        message.set_full_message("full");
+        message.set_timestamp(Utc.with_ymd_and_hms(2000, 1, 1, 1, 2, 3).unwrap());
+        message.set_metadata("key", 1).unwrap();
+
+        let wire_msg = WireMessage {
+            host: "host_value".into(),
+            message,
+            cache: Default::default(),
+        };
+
+        let json = wire_msg.to_gelf().unwrap();
+        let parsed = WireMessage::from_gelf(&json).unwrap();
+
+        assert_eq!(parsed.host, "host_value");
+        assert_eq!(parsed.message, wire_msg.message);
+        assert_eq!(parsed.to_gelf().unwrap(), json);
+        assert!(WireMessage::from_gelf("{\"short_message\": \"no host\", \"level\": 1}").is_err());
+    }
+
+    #[test]
+    fn share_serialization_between_clones() {
+        let wire_msg = WireMessage {
+            host: "host_value".into(),
+            message: Message::new("short"),
+            cache: Default::default(),
+        };
+        let clone = wire_msg.clone();
+
+        let gzip = MessageCompression::Gzip { level: 1 };
+        let compressed = wire_msg.to_compressed_gelf(gzip).unwrap();
+
+        assert!(clone.cache.gelf.get().is_some());
+        assert_eq!(clone.cache.compressed.lock().unwrap().len(), 1);
+        assert_eq!(clone.to_compressed_gelf(gzip).unwrap(), compressed);
+        assert_eq!(clone.to_compressed_gelf(MessageCompression::None).unwrap(), wire_msg.to_gelf().unwrap().into_bytes());
+        assert_eq!(wire_msg.cache.compressed.lock().unwrap().len(), 1);
     }
 }